@@ -0,0 +1,54 @@
+const MAX_DEPTH: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CallKind {
+    Call,
+    Restart,
+    Interrupt,
+}
+
+#[derive(Clone, Copy)]
+pub struct StackFrame {
+    pub kind: CallKind,
+    pub caller: u16, // the call instruction, or the instruction an interrupt came before
+    pub target: u16,
+    pub bank: Option<usize>, // rom bank of the target when it was entered
+    pub return_address: u16,
+    pub sp: u16, // where the return address was pushed
+}
+
+/// The calls the cpu is in, reconstructed from calls, restarts, interrupts and returns.
+pub struct CallStack {
+    pub frames: Vec<StackFrame>, // outermost first
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack { frames: Vec::new() }
+    }
+
+    pub fn push(&mut self, frame: StackFrame) {
+        // code that never returns, e.g. by resetting SP, would grow the stack forever
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    /// A return with SP pointing at the return address.
+    pub fn ret(&mut self, sp: u16) {
+        // frames below SP were left without a return, e.g. by popping the return address
+        while self.frames.last().is_some_and(|frame| frame.sp < sp) {
+            self.frames.pop();
+        }
+        if self.frames.last().is_some_and(|frame| frame.sp == sp) {
+            self.frames.pop();
+        }
+    }
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
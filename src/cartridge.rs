@@ -1,32 +1,80 @@
+use crate::patch::apply_patch;
 use crate::traits::{Memory, TestBit};
-use std::fs::{create_dir_all, read, write, File};
-use std::io::{Read, Result, Seek, SeekFrom};
+use std::fs::{create_dir_all, read, write};
+use std::io::{Error, ErrorKind, Result};
 use std::path::Path;
 
 pub trait Cartridge: Memory {
     fn serialize(&self) -> Vec<u8>;
     fn deserialize(&mut self, data: Vec<u8>);
+
+    // the whole rom and external ram, for debug views
+    fn rom(&self) -> &[u8];
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    // bank mapped to 0x4000-0x7FFF
+    fn rom_bank(&self) -> usize {
+        1
+    }
 }
 
+const REGISTER_TITLE: usize = 0x0134;
+const REGISTER_NEW_LICENSEE: usize = 0x0144;
+const REGISTER_SGB_FLAG: usize = 0x0146;
 const REGISTER_CARTRIDGE_TYPE: usize = 0x0147;
 const REGISTER_ROM_SIZE: usize = 0x0148;
 const REGISTER_RAM_SIZE: usize = 0x0149;
+const REGISTER_OLD_LICENSEE: usize = 0x014B;
+const TITLE_LENGTH: usize = 16;
+const HEADER_END: usize = 0x0150;
 const ROM_BANK_SIZE: usize = 0x4000;
 const RAM_BANK_SIZE: usize = 0x2000;
 
-pub fn load_rom(path: &str) -> Result<Box<dyn Cartridge>> {
-    let mut file = File::open(path)?;
-    file.seek(SeekFrom::Start(REGISTER_CARTRIDGE_TYPE as u64))?;
-    let mut buffer = [0_u8; 1];
-    file.read_exact(&mut buffer)?;
-    println!("Cartridge type: {:#04X}", buffer[0]);
-    match buffer[0] {
-        0x00 | 0x08 | 0x09 => Ok(Box::new(NoMBC::load(path))),
-        0x01 | 0x02 | 0x03 => Ok(Box::new(MBC1::load(path))),
-        0x05 | 0x06 => Ok(Box::new(MBC2::load(path))),
-        0x0F | 0x10 | 0x11 | 0x12 | 0x13 => Ok(Box::new(MBC3::load(path))),
-        0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E => Ok(Box::new(MBC5::load(path))),
-        _ => panic!("Unsupported cartridge type!"),
+/// Reads a rom file and applies an IPS, UPS or BPS patch to it.
+pub fn read_rom(path: &str, patch: Option<&str>) -> Result<Vec<u8>> {
+    let rom = read(path)?;
+    match patch {
+        Some(patch) => {
+            let rom = apply_patch(&rom, &read(patch)?)?;
+            println!("Applied patch {}", patch);
+            Ok(rom)
+        }
+        None => Ok(rom),
+    }
+}
+
+pub fn load_rom(path: &str, patch: Option<&str>) -> Result<Box<dyn Cartridge>> {
+    let rom = read_rom(path, patch)?;
+    // a patch can change the size, so the header is checked against what was read
+    if rom.len() < HEADER_END {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Rom is too short"));
+    }
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+    let rom_size = get_rom_size(rom[REGISTER_ROM_SIZE])
+        .ok_or_else(|| invalid(format!("Unknown rom size: {:#04X}", rom[REGISTER_ROM_SIZE])))?;
+    if rom.len() != rom_size {
+        return Err(invalid(format!(
+            "Rom is {} bytes, but the header says {}",
+            rom.len(),
+            rom_size
+        )));
+    }
+    let ram_size = get_ram_size(rom[REGISTER_RAM_SIZE])
+        .ok_or_else(|| invalid(format!("Unknown ram size: {:#04X}", rom[REGISTER_RAM_SIZE])))?;
+
+    let kind = rom[REGISTER_CARTRIDGE_TYPE];
+    println!("Cartridge type: {:#04X}", kind);
+    match kind {
+        0x00 | 0x08 | 0x09 => Ok(Box::new(NoMBC::new(rom, ram_size))),
+        0x01 | 0x02 | 0x03 => Ok(Box::new(MBC1::new(rom, ram_size))),
+        0x05 | 0x06 => Ok(Box::new(MBC2::new(rom))),
+        0x0F | 0x10 | 0x11 | 0x12 | 0x13 => Ok(Box::new(MBC3::new(rom, ram_size))),
+        0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E => Ok(Box::new(MBC5::new(rom, ram_size))),
+        _ => Err(invalid(format!(
+            "Unsupported cartridge type: {:#04X}",
+            kind
+        ))),
     }
 }
 
@@ -47,29 +95,63 @@ pub fn load_state(cartridge: &mut Box<dyn Cartridge>, path: &str) -> Result<()>
     Ok(())
 }
 
-fn get_rom_size(value: u8) -> usize {
+/// The game title from the header, without the padding.
+pub fn title(cartridge: &dyn Cartridge) -> String {
+    (REGISTER_TITLE..REGISTER_TITLE + TITLE_LENGTH)
+        .map(|address| cartridge.read(address))
+        .take_while(|byte| byte.is_ascii_graphic() || *byte == b' ')
+        .map(|byte| byte as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Sum of the title bytes, used by the CGB boot ROM to pick palettes for DMG games.
+pub fn title_checksum(cartridge: &dyn Cartridge) -> u8 {
+    (REGISTER_TITLE..REGISTER_TITLE + TITLE_LENGTH).fold(0_u8, |sum, address| {
+        sum.wrapping_add(cartridge.read(address))
+    })
+}
+
+pub fn is_nintendo_licensed(cartridge: &dyn Cartridge) -> bool {
+    match cartridge.read(REGISTER_OLD_LICENSEE) {
+        0x01 => true,
+        0x33 => {
+            cartridge.read(REGISTER_NEW_LICENSEE) == b'0'
+                && cartridge.read(REGISTER_NEW_LICENSEE + 1) == b'1'
+        }
+        _ => false,
+    }
+}
+
+/// SGB functions can only be used if the header enables them.
+pub fn supports_sgb(cartridge: &dyn Cartridge) -> bool {
+    cartridge.read(REGISTER_SGB_FLAG) == 0x03 && cartridge.read(REGISTER_OLD_LICENSEE) == 0x33
+}
+
+fn get_rom_size(value: u8) -> Option<usize> {
     match value {
-        0x00 => ROM_BANK_SIZE * 2,
-        0x01 => ROM_BANK_SIZE * 4,
-        0x02 => ROM_BANK_SIZE * 8,
-        0x03 => ROM_BANK_SIZE * 16,
-        0x04 => ROM_BANK_SIZE * 32,
-        0x05 => ROM_BANK_SIZE * 64,
-        0x06 => ROM_BANK_SIZE * 128,
-        0x07 => ROM_BANK_SIZE * 256,
-        0x08 => ROM_BANK_SIZE * 512,
-        _ => panic!("Unused rom size!"),
+        0x00 => Some(ROM_BANK_SIZE * 2),
+        0x01 => Some(ROM_BANK_SIZE * 4),
+        0x02 => Some(ROM_BANK_SIZE * 8),
+        0x03 => Some(ROM_BANK_SIZE * 16),
+        0x04 => Some(ROM_BANK_SIZE * 32),
+        0x05 => Some(ROM_BANK_SIZE * 64),
+        0x06 => Some(ROM_BANK_SIZE * 128),
+        0x07 => Some(ROM_BANK_SIZE * 256),
+        0x08 => Some(ROM_BANK_SIZE * 512),
+        _ => None,
     }
 }
 
-fn get_ram_size(value: u8) -> usize {
+fn get_ram_size(value: u8) -> Option<usize> {
     match value {
-        0x00 => 0,
-        0x02 => RAM_BANK_SIZE * 1,
-        0x03 => RAM_BANK_SIZE * 4,
-        0x04 => RAM_BANK_SIZE * 16,
-        0x05 => RAM_BANK_SIZE * 8,
-        _ => panic!("Unused ram size!"),
+        0x00 => Some(0),
+        0x02 => Some(RAM_BANK_SIZE),
+        0x03 => Some(RAM_BANK_SIZE * 4),
+        0x04 => Some(RAM_BANK_SIZE * 16),
+        0x05 => Some(RAM_BANK_SIZE * 8),
+        _ => None,
     }
 }
 
@@ -79,11 +161,7 @@ struct NoMBC {
 }
 
 impl NoMBC {
-    fn load(path: &str) -> Self {
-        let rom: Vec<u8> = read(path).unwrap();
-        let rom_size = get_rom_size(rom[REGISTER_ROM_SIZE]);
-        assert!(rom.len() == rom_size);
-        let ram_size = get_ram_size(rom[REGISTER_RAM_SIZE]);
+    fn new(rom: Vec<u8>, ram_size: usize) -> Self {
         let ram = vec![0; ram_size];
         NoMBC { rom, ram }
     }
@@ -93,15 +171,17 @@ impl Memory for NoMBC {
     fn read(&self, address: usize) -> u8 {
         match address {
             0x0000..=0x7FFF => self.rom[address],
-            0xA000..=0xBFFF => self.ram[address - 0xA000],
+            // carts without ram read open bus
+            0xA000..=0xBFFF => self.ram.get(address - 0xA000).copied().unwrap_or(0xFF),
             _ => panic!("Invalid address read!"),
         }
     }
 
     fn write(&mut self, address: usize, data: u8) {
-        match address {
-            0xA000..=0xBFFF => self.ram[address - 0xA000] = data,
-            _ => {}
+        if (0xA000..=0xBFFF).contains(&address) {
+            if let Some(byte) = self.ram.get_mut(address - 0xA000) {
+                *byte = data;
+            }
         }
     }
 }
@@ -114,6 +194,18 @@ impl Cartridge for NoMBC {
     fn serialize(&self) -> Vec<u8> {
         self.ram.clone()
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 }
 
 struct MBC1 {
@@ -127,11 +219,7 @@ struct MBC1 {
 }
 
 impl MBC1 {
-    fn load(path: &str) -> Self {
-        let rom: Vec<u8> = read(path).unwrap();
-        let rom_size = get_rom_size(rom[REGISTER_ROM_SIZE]);
-        assert!(rom.len() == rom_size);
-        let ram_size = get_ram_size(rom[REGISTER_RAM_SIZE]);
+    fn new(rom: Vec<u8>, ram_size: usize) -> Self {
         let ram = vec![0; ram_size];
         MBC1 {
             rom,
@@ -154,7 +242,10 @@ impl Memory for MBC1 {
             }
             0xA000..=0xBFFF => {
                 let bank = self.ram_bank * RAM_BANK_SIZE;
-                self.ram[bank + address - 0xA000]
+                self.ram
+                    .get(bank + address - 0xA000)
+                    .copied()
+                    .unwrap_or(0xFF)
             }
             _ => panic!("Invalid address read!"),
         }
@@ -179,7 +270,9 @@ impl Memory for MBC1 {
             0xA000..=0xBFFF => {
                 if self.enable_ram {
                     let bank = self.ram_bank * RAM_BANK_SIZE;
-                    self.ram[bank + address - 0xA000] = data;
+                    if let Some(byte) = self.ram.get_mut(bank + address - 0xA000) {
+                        *byte = data;
+                    }
                 }
             }
             _ => {}
@@ -188,6 +281,10 @@ impl Memory for MBC1 {
 }
 
 impl Cartridge for MBC1 {
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
     fn deserialize(&mut self, data: Vec<u8>) {
         self.ram = data;
     }
@@ -195,6 +292,18 @@ impl Cartridge for MBC1 {
     fn serialize(&self) -> Vec<u8> {
         self.ram.clone()
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 }
 
 struct MBC2 {
@@ -206,10 +315,7 @@ struct MBC2 {
 }
 
 impl MBC2 {
-    fn load(path: &str) -> Self {
-        let rom: Vec<u8> = read(path).unwrap();
-        let rom_size = get_rom_size(rom[REGISTER_ROM_SIZE]);
-        assert!(rom.len() == rom_size);
+    fn new(rom: Vec<u8>) -> Self {
         MBC2 {
             rom,
             ram: [0; 256],
@@ -254,6 +360,10 @@ impl Memory for MBC2 {
 }
 
 impl Cartridge for MBC2 {
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
     fn deserialize(&mut self, data: Vec<u8>) {
         self.ram = data.try_into().unwrap();
     }
@@ -261,6 +371,18 @@ impl Cartridge for MBC2 {
     fn serialize(&self) -> Vec<u8> {
         self.ram.to_vec()
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 }
 
 struct MBC3 {
@@ -276,11 +398,7 @@ struct MBC3 {
 }
 
 impl MBC3 {
-    fn load(path: &str) -> Self {
-        let rom: Vec<u8> = read(path).unwrap();
-        let rom_size = get_rom_size(rom[REGISTER_ROM_SIZE]);
-        assert!(rom.len() == rom_size);
-        let ram_size = get_ram_size(rom[REGISTER_RAM_SIZE]);
+    fn new(rom: Vec<u8>, ram_size: usize) -> Self {
         let ram = vec![0; ram_size];
         MBC3 {
             rom,
@@ -307,7 +425,10 @@ impl Memory for MBC3 {
                 if self.ram_banking_mode {
                     if self.ram_enabled {
                         let bank = self.ram_bank * RAM_BANK_SIZE;
-                        self.ram[bank + address - 0xA000]
+                        self.ram
+                            .get(bank + address - 0xA000)
+                            .copied()
+                            .unwrap_or(0xFF)
                     } else {
                         0xFF
                     }
@@ -347,7 +468,9 @@ impl Memory for MBC3 {
                 if self.ram_banking_mode {
                     if self.ram_enabled {
                         let bank = self.ram_bank * RAM_BANK_SIZE;
-                        self.ram[bank + address - 0xA000] = data;
+                        if let Some(byte) = self.ram.get_mut(bank + address - 0xA000) {
+                            *byte = data;
+                        }
                     }
                 } else {
                     match self.rtc_select {
@@ -366,6 +489,10 @@ impl Memory for MBC3 {
 }
 
 impl Cartridge for MBC3 {
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
     fn deserialize(&mut self, data: Vec<u8>) {
         self.ram = data;
     }
@@ -373,6 +500,18 @@ impl Cartridge for MBC3 {
     fn serialize(&self) -> Vec<u8> {
         self.ram.clone()
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 }
 
 struct MBC5 {
@@ -385,11 +524,7 @@ struct MBC5 {
 }
 
 impl MBC5 {
-    fn load(path: &str) -> Self {
-        let rom: Vec<u8> = read(path).unwrap();
-        let rom_size = get_rom_size(rom[REGISTER_ROM_SIZE]);
-        assert!(rom.len() == rom_size);
-        let ram_size = get_ram_size(rom[REGISTER_RAM_SIZE]);
+    fn new(rom: Vec<u8>, ram_size: usize) -> Self {
         let ram = vec![0; ram_size];
         MBC5 {
             rom,
@@ -412,7 +547,10 @@ impl Memory for MBC5 {
             0xA000..=0xBFFF => {
                 if self.enable_ram {
                     let bank = self.ram_bank * RAM_BANK_SIZE;
-                    self.ram[bank + address - 0xA000]
+                    self.ram
+                        .get(bank + address - 0xA000)
+                        .copied()
+                        .unwrap_or(0xFF)
                 } else {
                     0xFF
                 }
@@ -437,7 +575,9 @@ impl Memory for MBC5 {
             0xA000..=0xBFFF => {
                 if self.enable_ram {
                     let bank = self.ram_bank * RAM_BANK_SIZE;
-                    self.ram[bank + address - 0xA000] = data;
+                    if let Some(byte) = self.ram.get_mut(bank + address - 0xA000) {
+                        *byte = data;
+                    }
                 }
             }
             _ => {}
@@ -446,6 +586,10 @@ impl Memory for MBC5 {
 }
 
 impl Cartridge for MBC5 {
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
     fn serialize(&self) -> Vec<u8> {
         let mut data = self.ram.clone();
         data.push(self.enable_ram as u8);
@@ -459,4 +603,16 @@ impl Cartridge for MBC5 {
         self.ram_bank = data[len - 2] as usize;
         self.ram = data[..len - 2].to_vec();
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 }
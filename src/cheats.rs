@@ -0,0 +1,327 @@
+use json::{object, JsonValue};
+use std::fmt;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CheatCode {
+    /// `TTVVLLHH`: writes VV to HHLL every frame, types 80-8F pick the cartridge ram bank.
+    GameShark {
+        bank: Option<usize>,
+        value: u8,
+        address: u16,
+    },
+    /// `VVA-AAA` or `VVA-AAA-CCC`: replaces a rom byte, only where it equals the compare byte.
+    GameGenie {
+        address: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+}
+
+impl CheatCode {
+    pub fn parse(text: &str) -> Result<CheatCode> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid cheat: {}", text));
+        let digits = text.replace('-', "");
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let digit = |index: usize| u8::from_str_radix(&digits[index..index + 1], 16).unwrap();
+        let byte = |index: usize| u8::from_str_radix(&digits[index..index + 2], 16).unwrap();
+
+        match (digits.len(), text.contains('-')) {
+            (8, false) => {
+                let kind = byte(0);
+                Ok(CheatCode::GameShark {
+                    bank: (kind & 0xF0 == 0x80).then_some(kind as usize & 0x0F),
+                    value: byte(2),
+                    address: u16::from_le_bytes([byte(4), byte(6)]),
+                })
+            }
+            // https://gbdev.gg8.se/wiki/articles/Gameshark_and_Game_Genie_codes
+            (6 | 9, true) => {
+                let address = ((digit(5) as u16 ^ 0xF) << 12)
+                    | (digit(2) as u16) << 8
+                    | (digit(3) as u16) << 4
+                    | digit(4) as u16;
+                // the digit in between is a checksum
+                let compare = (digits.len() == 9)
+                    .then(|| ((digit(6) << 4) | digit(8)).rotate_right(2) ^ 0xBA);
+                if address >= 0x8000 {
+                    return Err(invalid());
+                }
+                Ok(CheatCode::GameGenie {
+                    address,
+                    value: byte(0),
+                    compare,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+pub struct Cheat {
+    pub name: String,
+    pub code: String, // as entered
+    pub cheat: CheatCode,
+    pub enabled: bool,
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        write!(f, "{:<12} {:<3} {}", self.code, state, self.name)?;
+        match self.cheat {
+            CheatCode::GameShark {
+                bank,
+                value,
+                address,
+            } => match bank {
+                Some(bank) => write!(f, " ({:02X}:{:04X} = {:02X})", bank, address, value),
+                None => write!(f, " ({:04X} = {:02X})", address, value),
+            },
+            CheatCode::GameGenie {
+                address,
+                value,
+                compare,
+            } => match compare {
+                Some(compare) => write!(f, " ({:04X} = {:02X} if {:02X})", address, value, compare),
+                None => write!(f, " ({:04X} = {:02X})", address, value),
+            },
+        }
+    }
+}
+
+/// The cheats of a rom, stored as a JSON array like
+/// `[{ "name": "Infinite lives", "code": "010943C1", "enabled": true }]`.
+pub struct Cheats {
+    pub enabled: bool,
+    pub path: Option<String>, // saved to on every change
+    pub frame_count: u64,     // the frame the codes were last written in
+    list: Vec<Cheat>,
+    patches: Vec<(u16, u8, Option<u8>)>, // enabled game genie codes
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats {
+            enabled: true,
+            path: None,
+            frame_count: u64::MAX,
+            list: Vec::new(),
+            patches: Vec::new(),
+        }
+    }
+
+    /// Loads the cheats from a file, a file that doesn't exist yet has none.
+    /// Nothing changes if the file can't be read, so it isn't overwritten by the next save.
+    pub fn load(&mut self, path: &str) -> Result<()> {
+        let mut list = Vec::new();
+        if Path::new(path).exists() {
+            let data = read_to_string(path)?;
+            let root = json::parse(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            for entry in root.members() {
+                let code = entry["code"].as_str().unwrap_or_default();
+                list.push(Cheat {
+                    name: entry["name"].as_str().unwrap_or_default().to_string(),
+                    code: code.to_string(),
+                    cheat: CheatCode::parse(code)?,
+                    enabled: entry["enabled"].as_bool().unwrap_or(true),
+                });
+            }
+        }
+        self.path = Some(path.to_string());
+        self.list = list;
+        self.update_patches();
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(folder) = Path::new(path).parent() {
+            create_dir_all(folder)?;
+        }
+        let root = JsonValue::Array(
+            self.list
+                .iter()
+                .map(|cheat| {
+                    object! {
+                        name: cheat.name.as_str(),
+                        code: cheat.code.as_str(),
+                        enabled: cheat.enabled,
+                    }
+                })
+                .collect(),
+        );
+        write(path, json::stringify_pretty(root, 2))
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    /// Adds an enabled cheat and returns its index.
+    pub fn add(&mut self, code: &str, name: &str) -> Result<usize> {
+        self.list.push(Cheat {
+            name: name.to_string(),
+            code: code.to_uppercase(),
+            cheat: CheatCode::parse(code)?,
+            enabled: true,
+        });
+        self.changed()?;
+        Ok(self.list.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<Option<Cheat>> {
+        if index >= self.list.len() {
+            return Ok(None);
+        }
+        let cheat = self.list.remove(index);
+        self.changed()?;
+        Ok(Some(cheat))
+    }
+
+    /// Enables or disables a single cheat, returns false if there is none at the index.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Result<bool> {
+        let Some(cheat) = self.list.get_mut(index) else {
+            return Ok(false);
+        };
+        cheat.enabled = enabled;
+        self.changed()?;
+        Ok(true)
+    }
+
+    /// The game shark writes for this frame, as bank, address and value.
+    pub fn writes(&self) -> Vec<(Option<usize>, u16, u8)> {
+        self.list
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.cheat {
+                CheatCode::GameShark {
+                    bank,
+                    value,
+                    address,
+                } => Some((bank, address, value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A rom byte with the game genie codes applied.
+    pub fn patch(&self, address: u16, value: u8) -> u8 {
+        if !self.enabled {
+            return value;
+        }
+        self.patches
+            .iter()
+            .find(|(patch, _, compare)| *patch == address && compare.is_none_or(|c| c == value))
+            .map_or(value, |(_, patched, _)| *patched)
+    }
+
+    pub fn has_patches(&self) -> bool {
+        !self.patches.is_empty()
+    }
+
+    fn changed(&mut self) -> Result<()> {
+        self.update_patches();
+        self.save()
+    }
+
+    fn update_patches(&mut self) {
+        self.patches = self
+            .list
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.cheat {
+                CheatCode::GameGenie {
+                    address,
+                    value,
+                    compare,
+                } => Some((address, value, compare)),
+                _ => None,
+            })
+            .collect();
+    }
+}
+
+impl Default for Cheats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_game_shark_codes() {
+        let code = CheatCode::parse("01FF43C1").unwrap();
+        assert!(
+            code == CheatCode::GameShark {
+                bank: None,
+                value: 0xFF,
+                address: 0xC143,
+            }
+        );
+        // 80-8F write to a cartridge ram bank
+        let code = CheatCode::parse("8A0512A0").unwrap();
+        assert!(
+            code == CheatCode::GameShark {
+                bank: Some(0x0A),
+                value: 0x05,
+                address: 0xA012,
+            }
+        );
+        assert!(CheatCode::parse("01FF43C").is_err());
+        assert!(CheatCode::parse("01FF43CG").is_err());
+    }
+
+    #[test]
+    fn parses_game_genie_codes() {
+        // the top address nibble is stored xored with F
+        let code = CheatCode::parse("3EA-17B").unwrap();
+        assert!(
+            code == CheatCode::GameGenie {
+                address: 0x4A17,
+                value: 0x3E,
+                compare: None,
+            }
+        );
+        // C9 rotated right by two is 72, xored with BA is C8
+        let code = CheatCode::parse("00A-17B-C49").unwrap();
+        assert!(
+            code == CheatCode::GameGenie {
+                address: 0x4A17,
+                value: 0x00,
+                compare: Some(0xC8),
+            }
+        );
+        // only rom can be patched
+        assert!(CheatCode::parse("00A-177-C49").is_err());
+        assert!(CheatCode::parse("00A-17F").is_ok());
+    }
+
+    #[test]
+    fn keeps_the_list_if_loading_fails() {
+        let path = std::env::temp_dir().join(format!("gb-emu-cheats-{}.json", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let mut cheats = Cheats::new();
+        cheats.add("01FF43C1", "Lives").unwrap();
+
+        write(
+            &path,
+            r#"[{ "name": "Good", "code": "010943C1" }, { "name": "Bad", "code": "XYZ" }]"#,
+        )
+        .unwrap();
+        assert!(cheats.load(&path).is_err());
+        assert_eq!(cheats.list().len(), 1);
+        assert_eq!(cheats.list()[0].name, "Lives");
+        assert!(cheats.path.is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use crate::patch::find_patch;
+use crate::trace::TraceFormat;
+use std::io::{Error, ErrorKind, Result};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+pub const USAGE: &str = "Usage: gb-emu [ROM] [OPTIONS]
+       gb-emu disasm ROM [--bank N] [--sym PATH] [--patch PATH] [--output PATH]
+
+Options:
+    --save PATH       Battery save file, defaults to ./saves/<ROM name>.sav
+    --cheats PATH     Cheat list, defaults to ./cheats/<ROM name>.json
+    --patch PATH      IPS, UPS or BPS patch to apply, defaults to <ROM>.ips, .ups or .bps
+    --no-patch        Run the rom without a patch next to it
+    --sym PATH        Symbol file for the debugger, traces and disassembly, defaults to <ROM>.sym
+    --record PATH     Record an APNG video from the start
+    --printer DIR     Connect a Game Boy Printer that saves its pages to a folder, e.g. ./prints
+    --sgb             Use Super Game Boy features of games that support them
+    --colorize        Color DMG games like the Game Boy Color does
+    --trace PATH      Log every instruction to a file
+    --trace-format F  Trace format, doctor (default) or full
+    --trace-pc A-B    Only trace instructions between two hex addresses
+    --trace-bank N    Only trace instructions in rom bank N
+    --profile         Profile from the start, the report is saved to ./profiles on exit
+    --debug           Start paused in the debugger, type help for its commands
+    --gdb PORT        Wait for GDB to connect on a local port, e.g. target remote :2331
+    --headless        Run without a window, requires --frames, --debug or --gdb
+    --frames N        Number of frames to run in headless mode
+    --dump-vram       Save the tiles, tile maps and sprites as PNGs to ./vram on exit
+    --bank N          Only disassemble rom bank N
+    --output PATH     Write the disassembly to a file instead of the console
+    --help            Show this message";
+
+const DEFAULT_ROM: &str = "./roms/pikachu.gb";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Command {
+    Run,
+    Disassemble,
+}
+
+pub struct Options {
+    pub command: Command,
+    pub rom_path: String,
+    pub save_path: String,
+    pub cheat_path: String,
+    pub symbol_path: Option<String>,
+    pub patch_path: Option<String>,
+    pub record_path: Option<String>,
+    pub printer_dir: Option<String>,
+    pub sgb: bool,
+    pub colorize: bool,
+    pub trace_path: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_pc: Option<RangeInclusive<u16>>,
+    pub trace_bank: Option<usize>,
+    pub profile: bool,
+    pub debug: bool,
+    pub gdb_port: Option<u16>,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub dump_vram: bool,
+    pub bank: Option<usize>,
+    pub output_path: Option<String>,
+    pub help: bool,
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options> {
+    let mut rom_path = None;
+    let mut save_path = None;
+    let mut cheat_path = None;
+    let mut no_patch = false;
+    let mut options = Options {
+        command: Command::Run,
+        rom_path: String::new(),
+        save_path: String::new(),
+        cheat_path: String::new(),
+        symbol_path: None,
+        patch_path: None,
+        record_path: None,
+        printer_dir: None,
+        sgb: false,
+        colorize: false,
+        trace_path: None,
+        trace_format: TraceFormat::Doctor,
+        trace_pc: None,
+        trace_bank: None,
+        profile: false,
+        debug: false,
+        gdb_port: None,
+        headless: false,
+        frames: None,
+        dump_vram: false,
+        bank: None,
+        output_path: None,
+        help: false,
+    };
+
+    let mut args = args.into_iter().peekable();
+    if args.peek().is_some_and(|arg| arg == "disasm") {
+        args.next();
+        options.command = Command::Disassemble;
+    }
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| invalid(format!("Missing value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--save" => save_path = Some(value()?),
+            "--cheats" => cheat_path = Some(value()?),
+            "--sym" => options.symbol_path = Some(value()?),
+            "--patch" => options.patch_path = Some(value()?),
+            "--no-patch" => no_patch = true,
+            "--record" => options.record_path = Some(value()?),
+            "--printer" => options.printer_dir = Some(value()?),
+            "--sgb" => options.sgb = true,
+            "--colorize" => options.colorize = true,
+            "--trace" => options.trace_path = Some(value()?),
+            "--trace-format" => options.trace_format = TraceFormat::parse(&value()?)?,
+            "--trace-pc" => {
+                let range = value()?;
+                let (start, end) = range
+                    .split_once('-')
+                    .ok_or_else(|| invalid(format!("Invalid address range: {}", range)))?;
+                options.trace_pc = Some(parse_address(start)?..=parse_address(end)?);
+            }
+            "--trace-bank" => options.trace_bank = Some(parse_bank(&value()?)?),
+            "--profile" => options.profile = true,
+            "--bank" => options.bank = Some(parse_bank(&value()?)?),
+            "--output" => options.output_path = Some(value()?),
+            "--debug" => options.debug = true,
+            "--gdb" => {
+                let port = value()?;
+                let port = port
+                    .parse()
+                    .map_err(|_| invalid(format!("Invalid port: {}", port)))?;
+                options.gdb_port = Some(port);
+            }
+            "--headless" => options.headless = true,
+            "--dump-vram" => options.dump_vram = true,
+            "--frames" => {
+                let frames = value()?;
+                let frames = frames
+                    .parse()
+                    .map_err(|_| invalid(format!("Invalid number of frames: {}", frames)))?;
+                options.frames = Some(frames);
+            }
+            "--help" | "-h" => options.help = true,
+            _ if arg.starts_with('-') => return Err(invalid(format!("Unknown option: {}", arg))),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(invalid(format!("Unexpected argument: {}", arg))),
+        }
+    }
+
+    let interactive = options.debug || options.gdb_port.is_some();
+    if options.headless && options.frames.is_none() && !interactive {
+        return Err(invalid(
+            "--headless requires --frames, --debug or --gdb".to_string(),
+        ));
+    }
+
+    options.rom_path = rom_path.unwrap_or_else(|| DEFAULT_ROM.to_string());
+    let name = Path::new(&options.rom_path)
+        .file_stem()
+        .map_or("game".into(), |stem| stem.to_string_lossy());
+    options.save_path = save_path.unwrap_or_else(|| format!("./saves/{}.sav", name));
+    options.cheat_path = cheat_path.unwrap_or_else(|| format!("./cheats/{}.json", name));
+    if options.patch_path.is_none() && !no_patch {
+        options.patch_path = find_patch(&options.rom_path);
+    }
+    if options.symbol_path.is_none() {
+        // a symbol file next to the rom is used when there is one
+        let path = Path::new(&options.rom_path).with_extension("sym");
+        if path.exists() {
+            options.symbol_path = Some(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(options)
+}
+
+// hexadecimal, with an optional 0x or $ prefix
+pub fn parse_address(text: &str) -> Result<u16> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| invalid(format!("Invalid address: {}", text)))
+}
+
+fn parse_bank(text: &str) -> Result<usize> {
+    text.parse()
+        .map_err(|_| invalid(format!("Invalid bank: {}", text)))
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
@@ -1,4 +1,10 @@
-use crate::{mmu::MMU, traits::*};
+use crate::{
+    callstack::{CallKind, CallStack, StackFrame},
+    mmu::MMU,
+    trace::Tracer,
+    traits::*,
+    watchpoint::Access,
+};
 
 pub const FLAG_ZERO: u8 = 7;
 pub const FLAG_SUBTRACT: u8 = 6;
@@ -25,6 +31,10 @@ pub struct CPU {
     pub halted: bool,
     pub pending_interrupt: Option<bool>,
     pub interrupt_master_enable: bool,
+
+    pub cycles: u64, // since power on
+    pub tracer: Option<Tracer>,
+    pub call_stack: CallStack,
 }
 
 /**
@@ -55,14 +65,37 @@ impl CPU {
             halted: false,
             pending_interrupt: None,
             interrupt_master_enable: false,
+            cycles: 0,
+            tracer: None,
+            call_stack: CallStack::new(),
         }
     }
 
     pub fn update(&mut self) -> u16 {
         let op_cycles = self.execute_next_opcode();
+        self.mmu.update_dma(op_cycles);
         self.mmu.interrupt_flag |= self.mmu.rtc.update_timers(op_cycles);
         self.mmu.interrupt_flag |= self.mmu.gpu.update_graphics(op_cycles);
+        if self.mmu.cheats.enabled {
+            self.mmu.apply_cheats();
+        }
+        if let Some(sgb) = self.mmu.joypad.sgb.as_mut() {
+            sgb.update(&self.mmu.gpu);
+        }
+        self.mmu.interrupt_flag |= self.mmu.serial.update(op_cycles);
         self.do_interrupts();
+        self.cycles += op_cycles as u64;
+        if self.mmu.watchpoints.active {
+            // execution is reported before the instruction runs
+            if !self.halted {
+                let opcode = self.mmu.peek(self.pc);
+                self.mmu.watchpoints.pc = self.pc;
+                self.mmu
+                    .watchpoints
+                    .check(Access::Execute, self.pc, opcode, opcode);
+            }
+            self.mmu.watchpoints.dispatch();
+        }
         op_cycles
     }
 
@@ -70,15 +103,23 @@ impl CPU {
         let cycles = if self.halted {
             4
         } else {
+            if let Some(mut tracer) = self.tracer.take() {
+                if let Err(e) = tracer.log(self) {
+                    println!("Failed to write trace: {}", e);
+                } else {
+                    self.tracer = Some(tracer);
+                }
+            }
+            self.mmu.watchpoints.pc = self.pc;
             let opcode = self.read_immediate_byte();
             let result = self.execute(opcode);
             result
         };
 
-        if self.pending_interrupt == Some(true) && self.mmu.read(self.pc - 1) != 0xFB {
+        if self.pending_interrupt == Some(true) && self.mmu.peek(self.pc - 1) != 0xFB {
             self.interrupt_master_enable = true;
             self.pending_interrupt = None;
-        } else if self.pending_interrupt == Some(false) && self.mmu.read(self.pc - 1) != 0xF3 {
+        } else if self.pending_interrupt == Some(false) && self.mmu.peek(self.pc - 1) != 0xF3 {
             self.interrupt_master_enable = false;
             self.pending_interrupt = None;
         }
@@ -101,6 +142,7 @@ impl CPU {
         self.interrupt_master_enable = false;
         self.mmu.interrupt_flag.reset_bit(id);
 
+        let caller = self.pc;
         self.push_stack(self.pc);
 
         match id {
@@ -111,6 +153,19 @@ impl CPU {
             4 => self.pc = 0x60,
             _ => panic!("Invalid interrupt id"),
         }
+        self.enter(CallKind::Interrupt, caller, caller);
+    }
+
+    // records a call after the return address was pushed and PC set to the target
+    fn enter(&mut self, kind: CallKind, caller: u16, return_address: u16) {
+        self.call_stack.push(StackFrame {
+            kind,
+            caller,
+            target: self.pc,
+            bank: self.mmu.rom_bank(self.pc),
+            return_address,
+            sp: self.sp,
+        });
     }
 
     fn af(&self) -> u16 {
@@ -130,7 +185,7 @@ impl CPU {
     }
 
     fn read_immediate_byte(&mut self) -> u8 {
-        let result = self.mmu.read(self.pc);
+        let result = self.mmu.fetch(self.pc);
         self.pc = self.pc.wrapping_add(1);
         result
     }
@@ -650,6 +705,7 @@ impl CPU {
             0x1F => without_zero!(rotate_right!(self.a)),
 
             0xD9 => {
+                self.call_stack.ret(self.sp);
                 self.pc = self.pop_stack();
                 self.interrupt_master_enable = true;
                 16
@@ -1203,21 +1259,34 @@ impl CPU {
     fn call(&mut self, flag: u8, use_condition: bool, condition: bool) -> u16 {
         let address = self.read_immediate_word();
         if !use_condition || self.f.test_bit(flag) == condition {
+            let return_address = self.pc;
             self.push_stack(self.pc);
             self.pc = address;
+            self.enter(
+                CallKind::Call,
+                return_address.wrapping_sub(3),
+                return_address,
+            );
             return 24;
         }
         12
     }
 
     fn restart(&mut self, offset: u16) -> u16 {
+        let return_address = self.pc;
         self.push_stack(self.pc);
         self.pc = offset;
+        self.enter(
+            CallKind::Restart,
+            return_address.wrapping_sub(1),
+            return_address,
+        );
         16
     }
 
     fn return_from_call(&mut self, flag: u8, use_condition: bool, condition: bool) -> u16 {
         if !use_condition || self.f.test_bit(flag) == condition {
+            self.call_stack.ret(self.sp);
             self.pc = self.pop_stack();
             return 20;
         }
@@ -1353,3 +1422,9 @@ impl CPU {
         result
     }
 }
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
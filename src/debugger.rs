@@ -0,0 +1,608 @@
+use crate::cli::parse_address;
+use crate::cpu::CPU;
+use crate::disasm::decode;
+use crate::memview::MemoryViewer;
+use crate::mmu::IO_REGISTERS;
+use crate::search::{Comparison, RamSearch, ValueKind};
+use crate::symbols::Symbols;
+use crate::traits::Register;
+use crate::watchpoint::{WatchHit, Watchpoint};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::ops::RangeInclusive;
+
+pub const HELP: &str = "Commands:
+    s, step [N]          Execute N instructions
+    f, frame             Run until the next frame or breakpoint
+    c, continue          Run until a breakpoint
+    b, break [BANK:]A    Set a breakpoint, e.g. break 0150 or break 01:4A2F
+    d, delete [N]        Delete breakpoint N, or all breakpoints
+    watch [rwx] RANGE    Break on reads, writes (default) or execution, e.g. watch C000-C0FF or watch rw LCDC
+    unwatch [N]          Delete watchpoint N, or all watchpoints
+    i, info              List the breakpoints and watchpoints
+    r, regs              Show the registers
+    bt, backtrace        Show the calls leading to PC
+    set REG V            Set a register, e.g. set hl C000 or set a 3F
+    m, mem A [N]         Show N bytes of memory, bytes changed since the last stop are highlighted
+    w, write A V...      Write bytes to memory
+    bank [rom|ram N|-]   Show another rom or ram bank in mem and write, - shows the mapped bank
+    io [NAME]            Show the io registers with their bits decoded
+    highlight            Toggle highlighting changed bytes
+    cheat                List the cheats
+    cheat add CODE [NAME]  Add a GameShark (01FF43C1) or Game Genie (00A-17B-C49) code
+    cheat on|off|delete N  Enable, disable or delete cheat N
+    search start [KIND]  Search all ram for a value, KIND is 8 (default), 16, bcd or bcd16
+    search FILTER        Keep the values that are same, changed, increased, decreased or equal to a value
+    search               List the remaining candidates
+    l, list [A] [N]      Disassemble N instructions, defaults to around PC
+    q, quit              Exit the emulator
+Addresses, banks and values are hexadecimal, addresses can also be labels from a symbol file.
+BCD values are decimal. An empty line repeats the last command.";
+
+const BYTES_PER_LINE: u16 = 16;
+const LIST_BEFORE: usize = 3;
+const LIST_LENGTH: usize = 10;
+const SEARCH_RESULTS: usize = 20;
+
+/// What the emulator should do after a debugger command.
+pub enum Action {
+    None,
+    Step(u32),
+    Frame,
+    Continue,
+    Quit,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Breakpoint {
+    pub bank: Option<usize>, // any bank when not set
+    pub address: u16,
+}
+
+impl Breakpoint {
+    /// Parses `ADDRESS`, `BANK:ADDRESS` or a label like `Main` or `Main+4`.
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Breakpoint> {
+        if let Some((bank, address)) = symbols.resolve(text) {
+            // labels in ram match in any bank
+            let bank = (address < 0x8000).then_some(bank);
+            return Ok(Breakpoint { bank, address });
+        }
+        let (bank, address) = match text.split_once(':') {
+            Some((bank, address)) => {
+                let bank = usize::from_str_radix(bank, 16)
+                    .map_err(|_| invalid(format!("Invalid bank: {}", bank)))?;
+                (Some(bank), address)
+            }
+            None => (None, text),
+        };
+        Ok(Breakpoint {
+            bank,
+            address: parse_address(address)?,
+        })
+    }
+
+    pub fn matches(&self, cpu: &CPU) -> bool {
+        cpu.pc == self.address
+            && self
+                .bank
+                .is_none_or(|bank| cpu.mmu.rom_bank(cpu.pc) == Some(bank))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02X}:{:04X}", bank, self.address),
+            None => write!(f, "{:04X}", self.address),
+        }
+    }
+}
+
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub paused: bool,
+    pub last_watch: Option<WatchHit>, // the watchpoint hit that paused execution
+    pub symbols: Symbols,
+    pub memory: MemoryViewer,
+    pub search: RamSearch,
+    resuming: bool, // the instruction at a breakpoint is executed when resuming
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            paused: false,
+            last_watch: None,
+            symbols: Symbols::new(),
+            memory: MemoryViewer::new(),
+            search: RamSearch::new(),
+            resuming: false,
+            last_command: String::new(),
+        }
+    }
+
+    /// Checks the breakpoints and watchpoints before the next instruction and pauses on a hit.
+    pub fn check(&mut self, cpu: &mut CPU) -> bool {
+        let resuming = std::mem::take(&mut self.resuming);
+        if self.watch_hit(cpu) {
+            self.paused = true;
+            return true;
+        }
+        // a halted cpu stays on the same address
+        if resuming || cpu.halted || !self.breakpoints.iter().any(|b| b.matches(cpu)) {
+            return false;
+        }
+        self.paused = true;
+        println!("Breakpoint at {}", self.location(cpu, cpu.pc));
+        true
+    }
+
+    /// Reports and clears the watchpoint hit of the last instruction, e.g. after a step.
+    pub fn watch_hit(&mut self, cpu: &mut CPU) -> bool {
+        let Some(hit) = cpu.mmu.watchpoints.triggered.take() else {
+            return false;
+        };
+        self.last_watch = Some(hit);
+        println!("Watchpoint: {}", hit);
+        true
+    }
+
+    /// Continues execution, starting with the instruction at a breakpoint.
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.resuming = true;
+    }
+
+    /// Runs a command line and returns what the emulator has to do for it.
+    pub fn execute(&mut self, cpu: &mut CPU, line: &str) -> Action {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+        match self.run_command(cpu, &line) {
+            Ok(action) => {
+                match action {
+                    Action::Continue => self.resume(),
+                    Action::Frame => self.resuming = true,
+                    _ => {}
+                }
+                if !matches!(action, Action::None) {
+                    self.memory.snapshot(&cpu.mmu);
+                }
+                action
+            }
+            Err(e) => {
+                println!("{}", e);
+                Action::None
+            }
+        }
+    }
+
+    fn run_command(&mut self, cpu: &mut CPU, line: &str) -> Result<Action> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let args = words.collect::<Vec<&str>>();
+
+        match command {
+            "" => {}
+            "s" | "step" => return Ok(Action::Step(parse_count(args.first(), 1)? as u32)),
+            "f" | "frame" => return Ok(Action::Frame),
+            "c" | "continue" => return Ok(Action::Continue),
+            "q" | "quit" => return Ok(Action::Quit),
+            "b" | "break" => {
+                let text = args
+                    .first()
+                    .ok_or_else(|| invalid("Missing address".to_string()))?;
+                let breakpoint = Breakpoint::parse(text, &self.symbols)?;
+                let index = match self.breakpoints.iter().position(|b| *b == breakpoint) {
+                    Some(index) => index,
+                    None => {
+                        self.breakpoints.push(breakpoint);
+                        self.breakpoints.len() - 1
+                    }
+                };
+                println!("Breakpoint {} at {}", index, breakpoint);
+            }
+            "d" | "delete" => match args.first() {
+                Some(index) => {
+                    let index = parse_count(Some(index), 0)?;
+                    if index >= self.breakpoints.len() {
+                        return Err(invalid(format!("No breakpoint {}", index)));
+                    }
+                    self.breakpoints.remove(index);
+                }
+                None => self.breakpoints.clear(),
+            },
+            "watch" => {
+                let (access, range) = match args[..] {
+                    [range] => ("w", range),
+                    [access, range] if access.chars().all(|c| "rwx".contains(c)) => (access, range),
+                    _ => return Err(invalid("Usage: watch [rwx] RANGE".to_string())),
+                };
+                let watchpoint = Watchpoint::new(parse_range(range)?, access);
+                let index = cpu.mmu.watchpoints.add(watchpoint);
+                println!(
+                    "Watchpoint {} at {}",
+                    index,
+                    cpu.mmu.watchpoints.list()[index]
+                );
+            }
+            "unwatch" => match args.first() {
+                Some(index) => {
+                    let index = parse_count(Some(index), 0)?;
+                    if cpu.mmu.watchpoints.remove(index).is_none() {
+                        return Err(invalid(format!("No watchpoint {}", index)));
+                    }
+                }
+                None => cpu.mmu.watchpoints.clear(),
+            },
+            "i" | "info" => {
+                for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+                    println!("Breakpoint {}: {}", index, breakpoint);
+                }
+                for (index, watchpoint) in cpu.mmu.watchpoints.list().iter().enumerate() {
+                    println!("Watchpoint {}: {}", index, watchpoint);
+                }
+            }
+            "r" | "regs" => println!("{}", registers(cpu)),
+            "bt" | "backtrace" => println!("{}", self.backtrace(cpu).join("\n")),
+            "set" => {
+                let [register, value] = args[..] else {
+                    return Err(invalid("Usage: set REG VALUE".to_string()));
+                };
+                set_register(cpu, register, parse_address(value)?)?;
+            }
+            "m" | "mem" => {
+                let address = self.address(args.first().unwrap_or(&""))?;
+                let length = parse_count(args.get(1), BYTES_PER_LINE as usize)? as u16;
+                println!("{}", self.memory.dump(&cpu.mmu, address, length).join("\n"));
+            }
+            "w" | "write" => {
+                let address = self.address(args.first().unwrap_or(&""))?;
+                if args.len() < 2 {
+                    return Err(invalid("Usage: write ADDRESS VALUE...".to_string()));
+                }
+                let values = args[1..]
+                    .iter()
+                    .map(|value| parse_byte(value))
+                    .collect::<Result<Vec<u8>>>()?;
+                for (offset, value) in values.into_iter().enumerate() {
+                    self.memory
+                        .write(&mut cpu.mmu, address.wrapping_add(offset as u16), value)?;
+                }
+            }
+            "l" | "list" => {
+                let lines = match args.first() {
+                    Some(address) => {
+                        let count = parse_count(args.get(1), LIST_LENGTH)?;
+                        self.disassemble(cpu, self.address(address)?, count)
+                    }
+                    None => {
+                        let start = previous_instruction(cpu, cpu.pc, LIST_BEFORE);
+                        self.disassemble(cpu, start, LIST_LENGTH)
+                    }
+                };
+                println!("{}", lines.join("\n"));
+            }
+            "bank" => {
+                match args[..] {
+                    [] => {}
+                    [memory, bank] => {
+                        let bank = match bank {
+                            "-" => None,
+                            bank => Some(
+                                usize::from_str_radix(bank, 16)
+                                    .map_err(|_| invalid(format!("Invalid bank: {}", bank)))?,
+                            ),
+                        };
+                        match memory {
+                            "rom" => self.memory.rom_bank = bank,
+                            "ram" => self.memory.ram_bank = bank,
+                            _ => return Err(invalid("Usage: bank [rom|ram BANK|-]".to_string())),
+                        }
+                    }
+                    _ => return Err(invalid("Usage: bank [rom|ram BANK|-]".to_string())),
+                }
+                let bank = |bank: Option<usize>| {
+                    bank.map_or("mapped".to_string(), |b| format!("{:02X}", b))
+                };
+                println!(
+                    "ROM bank {}, RAM bank {}",
+                    bank(self.memory.rom_bank),
+                    bank(self.memory.ram_bank)
+                );
+            }
+            "io" => {
+                let lines = self.memory.io_panel(&cpu.mmu, args.first().copied());
+                if lines.is_empty() {
+                    return Err(invalid(format!("Unknown register: {}", args[0])));
+                }
+                println!("{}", lines.join("\n"));
+            }
+            "highlight" => {
+                self.memory.highlight = !self.memory.highlight;
+                println!(
+                    "Highlighting {}",
+                    if self.memory.highlight { "on" } else { "off" }
+                );
+            }
+            "cheat" => {
+                let cheats = &mut cpu.mmu.cheats;
+                match args[..] {
+                    [] => {
+                        for (index, cheat) in cheats.list().iter().enumerate() {
+                            println!("Cheat {}: {}", index, cheat);
+                        }
+                    }
+                    ["add", code, ..] => {
+                        let index = cheats.add(code, &args[2..].join(" "))?;
+                        println!("Cheat {}: {}", index, cheats.list()[index]);
+                    }
+                    [command @ ("on" | "off" | "delete"), index] => {
+                        let index = parse_count(Some(&index), 0)?;
+                        let found = match command {
+                            "delete" => cheats.remove(index)?.is_some(),
+                            _ => cheats.set_enabled(index, command == "on")?,
+                        };
+                        if !found {
+                            return Err(invalid(format!("No cheat {}", index)));
+                        }
+                    }
+                    _ => {
+                        return Err(invalid(
+                            "Usage: cheat [add CODE [NAME] | on N | off N | delete N]".to_string(),
+                        ))
+                    }
+                }
+            }
+            "search" => {
+                let search = &mut self.search;
+                match args[..] {
+                    [] => {}
+                    ["start", ..] => {
+                        let kind = match args.get(1).copied().unwrap_or("8") {
+                            "8" => ValueKind::Byte,
+                            "16" => ValueKind::Word,
+                            "bcd" => ValueKind::Bcd,
+                            "bcd16" => ValueKind::BcdWord,
+                            kind => return Err(invalid(format!("Unknown value kind: {}", kind))),
+                        };
+                        search.start(&cpu.mmu, kind);
+                    }
+                    [filter] => {
+                        if !search.is_started() {
+                            return Err(invalid("Start a search with search start".to_string()));
+                        }
+                        let comparison = match filter {
+                            "same" => Comparison::Unchanged,
+                            "changed" => Comparison::Changed,
+                            "increased" => Comparison::Increased,
+                            "decreased" => Comparison::Decreased,
+                            value => Comparison::Equals(parse_value(search.kind, value)?),
+                        };
+                        search.filter(&cpu.mmu, comparison);
+                    }
+                    _ => return Err(invalid("Usage: search [start [KIND] | FILTER]".to_string())),
+                }
+                let candidates = search.candidates();
+                println!("{} candidates", candidates.len());
+                // long lists are only shown when asked for
+                let shown = if args.is_empty() || candidates.len() <= SEARCH_RESULTS {
+                    SEARCH_RESULTS
+                } else {
+                    0
+                };
+                for (location, value) in candidates.iter().take(shown) {
+                    println!("{:<7}  {}", location, format_value(search.kind, *value));
+                }
+            }
+            "h" | "help" => println!("{}", HELP),
+            _ => return Err(invalid(format!("Unknown command: {}, try help", command))),
+        }
+        Ok(Action::None)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// Disassembles `count` instructions from an address, PC is marked with `>`.
+    pub fn disassemble(&self, cpu: &CPU, address: u16, count: usize) -> Vec<String> {
+        let mut address = address;
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            let memory = [0, 1, 2].map(|i| cpu.mmu.peek(address.wrapping_add(i)));
+            let instruction = decode(&memory, address);
+            let bytes = memory[..instruction.length as usize]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<String>>()
+                .join(" ");
+            let marker = if address == cpu.pc { '>' } else { ' ' };
+            lines.push(format!(
+                "{} {}  {:<8}  {}",
+                marker,
+                self.location(cpu, address),
+                bytes,
+                instruction.mnemonic
+            ));
+            address = address.wrapping_add(instruction.length);
+        }
+        lines
+    }
+
+    /// The calls leading to PC, innermost first.
+    pub fn backtrace(&self, cpu: &CPU) -> Vec<String> {
+        let frames = &cpu.call_stack.frames;
+        let mut lines = vec![format!("#0  {}", self.location(cpu, cpu.pc))];
+        for (index, frame) in frames.iter().enumerate().rev() {
+            // the caller runs in the bank its own frame was entered with
+            let bank = match frame.caller {
+                0x0000..=0x3FFF => Some(0),
+                _ => index
+                    .checked_sub(1)
+                    .map_or(cpu.mmu.rom_bank(frame.caller), |parent| frames[parent].bank),
+            };
+            lines.push(format!(
+                "#{}  {}  {:?} {}",
+                frames.len() - index,
+                self.symbols.format(bank, frame.caller),
+                frame.kind,
+                self.symbols.format(frame.bank, frame.target)
+            ));
+        }
+        lines
+    }
+
+    // bank and address or label, the bank is unknown outside of the rom
+    fn location(&self, cpu: &CPU, address: u16) -> String {
+        self.symbols.format(cpu.mmu.rom_bank(address), address)
+    }
+
+    // a label or a hexadecimal address
+    fn address(&self, text: &str) -> Result<u16> {
+        match self.symbols.resolve(text) {
+            Some((_, address)) => Ok(address),
+            None => parse_address(text),
+        }
+    }
+}
+
+/// The registers and flags in one line.
+pub fn registers(cpu: &CPU) -> String {
+    let flags = [(7, 'Z'), (6, 'N'), (5, 'H'), (4, 'C')]
+        .iter()
+        .map(|(bit, name)| if cpu.f & (1 << bit) != 0 { *name } else { '-' })
+        .collect::<String>();
+    format!(
+        "AF:{:02X}{:02X} BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X} PC:{:04X} F:{} IME:{} HALT:{} CY:{}",
+        cpu.a,
+        cpu.f,
+        cpu.b,
+        cpu.c,
+        cpu.d,
+        cpu.e,
+        cpu.h,
+        cpu.l,
+        cpu.sp,
+        cpu.pc,
+        flags,
+        cpu.interrupt_master_enable as u8,
+        cpu.halted as u8,
+        cpu.cycles,
+    )
+}
+
+// instructions can't be decoded backwards, so the furthest start that lines up with the address is used
+fn previous_instruction(cpu: &CPU, address: u16, count: usize) -> u16 {
+    for distance in (1..=count as u16 * 3).rev() {
+        let Some(start) = address.checked_sub(distance) else {
+            continue;
+        };
+        let mut next = start as u32;
+        let mut instructions = 0;
+        while next < address as u32 {
+            let memory = [0, 1, 2].map(|i| cpu.mmu.peek((next as u16).wrapping_add(i)));
+            next += decode(&memory, next as u16).length as u32;
+            instructions += 1;
+        }
+        if next == address as u32 && instructions <= count {
+            return start;
+        }
+    }
+    address
+}
+
+fn set_register(cpu: &mut CPU, register: &str, value: u16) -> Result<()> {
+    let register = register.to_ascii_lowercase();
+    if register.len() == 1 && value > 0xFF {
+        return Err(invalid(format!(
+            "Value too large for {}: {:X}",
+            register, value
+        )));
+    }
+    let pair = |hi: &mut u8, lo: &mut u8| {
+        *hi = value.hi();
+        *lo = value.lo();
+    };
+    match register.as_str() {
+        "a" => cpu.a = value as u8,
+        "f" => cpu.f = value as u8 & 0xF0,
+        "b" => cpu.b = value as u8,
+        "c" => cpu.c = value as u8,
+        "d" => cpu.d = value as u8,
+        "e" => cpu.e = value as u8,
+        "h" => cpu.h = value as u8,
+        "l" => cpu.l = value as u8,
+        "af" => {
+            cpu.a = value.hi();
+            cpu.f = value.lo() & 0xF0;
+        }
+        "bc" => pair(&mut cpu.b, &mut cpu.c),
+        "de" => pair(&mut cpu.d, &mut cpu.e),
+        "hl" => pair(&mut cpu.h, &mut cpu.l),
+        "sp" => cpu.sp = value,
+        "pc" => cpu.pc = value,
+        _ => return Err(invalid(format!("Unknown register: {}", register))),
+    }
+    Ok(())
+}
+
+// an address, a range like C000-C0FF or the name of an io register
+fn parse_range(text: &str) -> Result<RangeInclusive<u16>> {
+    if let Some((address, _)) = IO_REGISTERS
+        .iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(text))
+    {
+        return Ok(*address..=*address);
+    }
+    match text.split_once('-') {
+        Some((start, end)) => Ok(parse_address(start)?..=parse_address(end)?),
+        None => {
+            let address = parse_address(text)?;
+            Ok(address..=address)
+        }
+    }
+}
+
+fn parse_byte(text: &str) -> Result<u8> {
+    let value = parse_address(text)?;
+    u8::try_from(value).map_err(|_| invalid(format!("Invalid byte: {}", text)))
+}
+
+// bcd values are entered and shown as the decimal number they encode
+fn parse_value(kind: ValueKind, text: &str) -> Result<u32> {
+    let value = match kind {
+        ValueKind::Byte | ValueKind::Word => u32::from_str_radix(text, 16),
+        ValueKind::Bcd | ValueKind::BcdWord => text.parse(),
+    };
+    value.map_err(|_| invalid(format!("Invalid value: {}", text)))
+}
+
+fn format_value(kind: ValueKind, value: u32) -> String {
+    match kind {
+        ValueKind::Byte => format!("{:02X}", value),
+        ValueKind::Word => format!("{:04X}", value),
+        ValueKind::Bcd | ValueKind::BcdWord => value.to_string(),
+    }
+}
+
+fn parse_count(text: Option<&&str>, default: usize) -> Result<usize> {
+    match text {
+        Some(text) => text
+            .parse()
+            .map_err(|_| invalid(format!("Invalid number: {}", text))),
+        None => Ok(default),
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,326 @@
+use crate::symbols::Symbols;
+use std::fmt::Write;
+
+// https://gbdev.io/gb-opcodes/optables/
+// Opcodes are decoded from their bit fields: xx yyy zzz, with yyy split into pp q.
+
+const BANK_SIZE: usize = 0x4000;
+const HEADER: std::ops::Range<u16> = 0x0104..0x0150;
+const DATA_PER_LINE: usize = 8;
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = [
+    "ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP ",
+];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const MISC: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+pub struct Instruction {
+    pub mnemonic: String,
+    pub length: u16,
+    pub cycles: u8,       // clock cycles, for conditional instructions when not taken
+    pub cycles_taken: u8, // clock cycles of a taken branch
+    pub target: Option<u16>, // address of jumps and calls
+}
+
+impl Instruction {
+    fn new(mnemonic: String, length: u16, cycles: u8) -> Instruction {
+        Instruction {
+            mnemonic,
+            length,
+            cycles,
+            cycles_taken: cycles,
+            target: None,
+        }
+    }
+
+    fn branch(
+        mnemonic: String,
+        length: u16,
+        cycles: u8,
+        cycles_taken: u8,
+        target: u16,
+    ) -> Instruction {
+        Instruction {
+            mnemonic,
+            length,
+            cycles,
+            cycles_taken,
+            target: Some(target),
+        }
+    }
+
+    fn with_taken(mut self, cycles_taken: u8) -> Instruction {
+        self.cycles_taken = cycles_taken;
+        self
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which was read from `address`.
+/// Missing operand bytes are read as zero.
+pub fn decode(bytes: &[u8], address: u16) -> Instruction {
+    let byte = |index: usize| bytes.get(index).copied().unwrap_or(0);
+    let opcode = byte(0);
+    let d8 = byte(1);
+    let d16 = u16::from_le_bytes([byte(1), byte(2)]);
+    let relative = address.wrapping_add(2).wrapping_add(d8 as i8 as u16);
+
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = (opcode & 7) as usize;
+    let (p, q) = (y >> 1, y & 1);
+    // (HL) operands take an extra memory access
+    let hl_cycles = |index: usize, extra: u8| if index == 6 { extra } else { 0 };
+
+    match (x, z) {
+        (0, 0) => match y {
+            0 => Instruction::new("NOP".to_string(), 1, 4),
+            1 => Instruction::new(format!("LD (${:04X}),SP", d16), 3, 20),
+            2 => Instruction::new("STOP".to_string(), 2, 4),
+            3 => Instruction::branch(format!("JR ${:04X}", relative), 2, 12, 12, relative),
+            _ => Instruction::branch(
+                format!("JR {},${:04X}", CC[y - 4], relative),
+                2,
+                8,
+                12,
+                relative,
+            ),
+        },
+        (0, 1) if q == 0 => Instruction::new(format!("LD {},${:04X}", RP[p], d16), 3, 12),
+        (0, 1) => Instruction::new(format!("ADD HL,{}", RP[p]), 1, 8),
+        (0, 2) => {
+            let pointer = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            if q == 0 {
+                Instruction::new(format!("LD {},A", pointer), 1, 8)
+            } else {
+                Instruction::new(format!("LD A,{}", pointer), 1, 8)
+            }
+        }
+        (0, 3) if q == 0 => Instruction::new(format!("INC {}", RP[p]), 1, 8),
+        (0, 3) => Instruction::new(format!("DEC {}", RP[p]), 1, 8),
+        (0, 4) => Instruction::new(format!("INC {}", R[y]), 1, 4 + hl_cycles(y, 8)),
+        (0, 5) => Instruction::new(format!("DEC {}", R[y]), 1, 4 + hl_cycles(y, 8)),
+        (0, 6) => Instruction::new(format!("LD {},${:02X}", R[y], d8), 2, 8 + hl_cycles(y, 4)),
+        (0, _) => Instruction::new(MISC[y].to_string(), 1, 4),
+        (1, 6) if y == 6 => Instruction::new("HALT".to_string(), 1, 4),
+        (1, _) => {
+            let cycles = 4 + hl_cycles(y, 4) + hl_cycles(z, 4);
+            Instruction::new(format!("LD {},{}", R[y], R[z]), 1, cycles)
+        }
+        (2, _) => Instruction::new(format!("{}{}", ALU[y], R[z]), 1, 4 + hl_cycles(z, 4)),
+        (_, 0) => match y {
+            0..=3 => Instruction::new(format!("RET {}", CC[y]), 1, 8).with_taken(20),
+            4 => Instruction::new(format!("LDH ($FF{:02X}),A", d8), 2, 12),
+            5 => Instruction::new(format!("ADD SP,{}", d8 as i8), 2, 16),
+            6 => Instruction::new(format!("LDH A,($FF{:02X})", d8), 2, 12),
+            _ => Instruction::new(format!("LD HL,SP{:+}", d8 as i8), 2, 12),
+        },
+        (_, 1) if q == 0 => Instruction::new(format!("POP {}", RP2[p]), 1, 12),
+        (_, 1) => match p {
+            0 => Instruction::new("RET".to_string(), 1, 16),
+            1 => Instruction::new("RETI".to_string(), 1, 16),
+            2 => Instruction::new("JP HL".to_string(), 1, 4),
+            _ => Instruction::new("LD SP,HL".to_string(), 1, 8),
+        },
+        (_, 2) => match y {
+            0..=3 => Instruction::branch(format!("JP {},${:04X}", CC[y], d16), 3, 12, 16, d16),
+            4 => Instruction::new("LD ($FF00+C),A".to_string(), 1, 8),
+            5 => Instruction::new(format!("LD (${:04X}),A", d16), 3, 16),
+            6 => Instruction::new("LD A,($FF00+C)".to_string(), 1, 8),
+            _ => Instruction::new(format!("LD A,(${:04X})", d16), 3, 16),
+        },
+        (_, 3) => match y {
+            0 => Instruction::branch(format!("JP ${:04X}", d16), 3, 16, 16, d16),
+            1 => decode_cb(d8),
+            6 => Instruction::new("DI".to_string(), 1, 4),
+            7 => Instruction::new("EI".to_string(), 1, 4),
+            _ => illegal(opcode),
+        },
+        (_, 4) if y < 4 => {
+            Instruction::branch(format!("CALL {},${:04X}", CC[y], d16), 3, 12, 24, d16)
+        }
+        (_, 5) if q == 0 => Instruction::new(format!("PUSH {}", RP2[p]), 1, 16),
+        (_, 5) if p == 0 => Instruction::branch(format!("CALL ${:04X}", d16), 3, 24, 24, d16),
+        (_, 6) => Instruction::new(format!("{}${:02X}", ALU[y], d8), 2, 8),
+        (_, 7) => {
+            let target = y as u16 * 8;
+            Instruction::branch(format!("RST ${:02X}", target), 1, 16, 16, target)
+        }
+        _ => illegal(opcode),
+    }
+}
+
+fn decode_cb(opcode: u8) -> Instruction {
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = (opcode & 7) as usize;
+    let hl = z == 6;
+    match x {
+        0 => Instruction::new(format!("{} {}", ROT[y], R[z]), 2, if hl { 16 } else { 8 }),
+        1 => Instruction::new(format!("BIT {},{}", y, R[z]), 2, if hl { 12 } else { 8 }),
+        2 => Instruction::new(format!("RES {},{}", y, R[z]), 2, if hl { 16 } else { 8 }),
+        _ => Instruction::new(format!("SET {},{}", y, R[z]), 2, if hl { 16 } else { 8 }),
+    }
+}
+
+// opcodes without an instruction lock up the CPU
+fn illegal(opcode: u8) -> Instruction {
+    Instruction::new(format!("DB ${:02X}", opcode), 1, 4)
+}
+
+// labels of bank 0
+fn label(address: u16) -> Option<String> {
+    let name = match address {
+        0x00..=0x38 if address.is_multiple_of(8) => return Some(format!("RST_{:02X}", address)),
+        0x40 => "VBLANK",
+        0x48 => "STAT",
+        0x50 => "TIMER",
+        0x58 => "SERIAL",
+        0x60 => "JOYPAD",
+        0x100 => "ENTRY",
+        0x104 => "HEADER_LOGO",
+        0x134 => "HEADER_TITLE",
+        0x143 => "HEADER_CGB_FLAG",
+        0x144 => "HEADER_NEW_LICENSEE",
+        0x146 => "HEADER_SGB_FLAG",
+        0x147 => "HEADER_CARTRIDGE_TYPE",
+        0x148 => "HEADER_ROM_SIZE",
+        0x149 => "HEADER_RAM_SIZE",
+        0x14A => "HEADER_DESTINATION",
+        0x14B => "HEADER_OLD_LICENSEE",
+        0x14C => "HEADER_VERSION",
+        0x14D => "HEADER_CHECKSUM",
+        0x14E => "HEADER_GLOBAL_CHECKSUM",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// Disassembles a whole rom, or only one bank of it, as a linear sweep.
+pub fn disassemble_rom(rom: &[u8], only_bank: Option<usize>, symbols: &Symbols) -> String {
+    let mut output = String::new();
+    for (bank, data) in rom.chunks(BANK_SIZE).enumerate() {
+        if only_bank.is_some_and(|only_bank| only_bank != bank) {
+            continue;
+        }
+        let base = if bank == 0 { 0x0000 } else { 0x4000 };
+        let bank_label = |address: u16| match symbols.label(Some(bank), address) {
+            Some(name) => Some(name.to_string()),
+            None if bank == 0 => label(address),
+            None => None,
+        };
+        // jumps from bank 0 into the switchable bank can't be resolved
+        let target_label = |target: u16| match target {
+            0x0000..=0x3FFF => bank_label(target).or_else(|| symbols.lookup(Some(0), target)),
+            0x4000..=0x7FFF if bank != 0 => symbols.lookup(Some(bank), target),
+            _ => symbols.lookup(None, target),
+        };
+        writeln!(output, "; bank {:02X}", bank).unwrap();
+
+        let mut offset = 0;
+        while offset < data.len() {
+            let address = base + offset as u16;
+            if let Some(label) = bank_label(address) {
+                writeln!(output, "\n{}:", label).unwrap();
+            }
+
+            // the header is data, it is shown up to the next label
+            let is_data = bank == 0 && HEADER.contains(&address);
+            let (length, text) = if is_data {
+                let length = (1..DATA_PER_LINE)
+                    .take_while(|i| {
+                        let next = address + *i as u16;
+                        HEADER.contains(&next) && bank_label(next).is_none()
+                    })
+                    .count()
+                    + 1;
+                let length = length.min(data.len() - offset);
+                let values = data[offset..offset + length]
+                    .iter()
+                    .map(|value| format!("${:02X}", value))
+                    .collect::<Vec<String>>();
+                (length, format!("DB {}", values.join(",")))
+            } else {
+                let instruction = decode(&data[offset..], address);
+                let length = (instruction.length as usize).min(data.len() - offset);
+                let target = instruction.target.and_then(target_label);
+                match target {
+                    Some(target) => (length, format!("{:<20}; {}", instruction.mnemonic, target)),
+                    None => (length, instruction.mnemonic),
+                }
+            };
+
+            let bytes = if is_data {
+                String::new()
+            } else {
+                data[offset..offset + length]
+                    .iter()
+                    .map(|value| format!("{:02X}", value))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            };
+            writeln!(
+                output,
+                "  {:02X}:{:04X}  {:<8}  {}",
+                bank, address, bytes, text
+            )
+            .unwrap();
+            offset += length;
+        }
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_instructions() {
+        // bytes, text, length, cycles and cycles of a taken branch, decoded at 0150
+        let instructions: [(&[u8], &str, u16, u8, u8); 16] = [
+            (&[0x00], "NOP", 1, 4, 4),
+            (&[0xCB, 0x7C], "BIT 7,H", 2, 8, 8),
+            (&[0xCB, 0x46], "BIT 0,(HL)", 2, 12, 12),
+            (&[0xCB, 0x36], "SWAP (HL)", 2, 16, 16),
+            (&[0xE0, 0x44], "LDH ($FF44),A", 2, 12, 12),
+            (&[0x20, 0xFE], "JR NZ,$0150", 2, 8, 12),
+            (&[0x18, 0x02], "JR $0154", 2, 12, 12),
+            (&[0xD3], "DB $D3", 1, 4, 4),
+            (&[0x01, 0x34, 0x12], "LD BC,$1234", 3, 12, 12),
+            (&[0x36, 0x12], "LD (HL),$12", 2, 12, 12),
+            (&[0x7E], "LD A,(HL)", 1, 8, 8),
+            (&[0x76], "HALT", 1, 4, 4),
+            (&[0xC4, 0x00, 0x40], "CALL NZ,$4000", 3, 12, 24),
+            (&[0xC8], "RET Z", 1, 8, 20),
+            (&[0xF8, 0xFE], "LD HL,SP-2", 2, 12, 12),
+            (&[0xFF], "RST $38", 1, 16, 16),
+        ];
+        for (bytes, text, length, cycles, cycles_taken) in instructions {
+            let instruction = decode(bytes, 0x0150);
+            assert_eq!(instruction.mnemonic, text);
+            assert_eq!(
+                (
+                    instruction.length,
+                    instruction.cycles,
+                    instruction.cycles_taken
+                ),
+                (length, cycles, cycles_taken),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_branch_targets() {
+        assert_eq!(decode(&[0x20, 0xFE], 0x0150).target, Some(0x0150));
+        assert_eq!(decode(&[0xC3, 0x50, 0x01], 0x0100).target, Some(0x0150));
+        assert_eq!(decode(&[0xFF], 0x0150).target, Some(0x0038));
+        assert_eq!(decode(&[0xE0, 0x44], 0x0150).target, None);
+    }
+}
@@ -1,19 +1,62 @@
 use std::io::Result;
 
-use crate::cartridge::{load_rom, load_state, save_state};
+use crate::cartridge::{load_rom, load_state, save_state, supports_sgb, title};
+use crate::cheats::Cheats;
+use crate::colorization::{colorize, combo_palettes};
 use crate::cpu::CPU;
+use crate::debugger::{Action, Debugger};
+use crate::filter::Filter;
+use crate::gdb::{GdbStub, Resume};
 use crate::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
 use crate::joypad;
+use crate::palette::{load_palettes, presets, Palette};
+use crate::png::framebuffer_to_png;
+use crate::printer::{PrintedPage, Printer};
+use crate::profiler::Profiler;
+use crate::recorder::Recorder;
+use crate::sgb::{SGB, SGB_HEIGHT, SGB_WIDTH};
+use crate::symbols::Symbols;
+use crate::trace::Tracer;
+use crate::viewer::{
+    render_maps, render_sprites, render_tiles, sprite_table, Image, TilePalette, View, VIEWS,
+};
+use crate::watchpoint::Watchpoints;
 use mini_gl_fb::glutin::dpi::LogicalSize;
 use mini_gl_fb::glutin::event::VirtualKeyCode as Key;
-use mini_gl_fb::glutin::event_loop::EventLoop;
-use mini_gl_fb::{get_fancy, ConfigBuilder};
+use mini_gl_fb::glutin::event::{ElementState, Event, KeyboardInput, WindowEvent};
+use mini_gl_fb::glutin::event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget};
+use mini_gl_fb::glutin::platform::run_return::EventLoopExtRunReturn;
+use mini_gl_fb::glutin::window::WindowId;
+use mini_gl_fb::{get_fancy, BasicInput, ConfigBuilder, GlutinBreakout};
+use std::fs::{create_dir_all, write};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct Emulator {
     cpu: CPU,
     rom_path: String,
     ram_path: String,
+    patch_path: Option<String>,
+    palette_path: String,
     speed: u128,
+    palettes: Vec<Palette>,
+    palette_index: usize,
+    colorization: bool,
+    sgb_mode: bool,
+    pub filter: Filter,
+    screenshot_dir: String,
+    recording_dir: String,
+    record_path: Option<String>,
+    recorder: Option<Recorder>,
+    debugger: Debugger,
+    gdb_port: Option<u16>,
+    gdb: Option<GdbStub>,
+    pub profiler: Profiler,
+    profile_dir: String,
+    pub tile_palette: TilePalette,
+    vram_dir: String,
+    dump_vram_on_exit: bool,
 }
 
 impl Emulator {
@@ -22,26 +65,104 @@ impl Emulator {
             cpu: CPU::new(),
             rom_path: rom_path.to_string(),
             ram_path: ram_path.to_string(),
+            patch_path: None,
+            palette_path: "./palettes.json".to_string(),
             speed: 100,
+            palettes: presets().into_iter().chain(combo_palettes()).collect(),
+            palette_index: 0,
+            colorization: false,
+            sgb_mode: false,
+            filter: Filter::new(),
+            screenshot_dir: "./screenshots".to_string(),
+            recording_dir: "./recordings".to_string(),
+            record_path: None,
+            recorder: None,
+            debugger: Debugger::new(),
+            gdb_port: None,
+            gdb: None,
+            profiler: Profiler::new(),
+            profile_dir: "./profiles".to_string(),
+            tile_palette: TilePalette::Background,
+            vram_dir: "./vram".to_string(),
+            dump_vram_on_exit: false,
         }
     }
 
-    pub fn run(&mut self) {
-        self.load_rom()
-            .unwrap_or_else(|e| println!("Failed to load rom: {}", e));
+    fn start(&mut self) {
+        // there is nothing to run without a rom
+        if let Err(e) = self.load_rom() {
+            println!("Failed to load rom: {}", e);
+            std::process::exit(1);
+        }
         self.load_save().unwrap_or_default();
+        self.load_palettes().unwrap_or_default();
+        if let Some(path) = self.record_path.clone() {
+            if let Err(e) = self.start_recording(Some(&path)) {
+                println!("Failed to start recording: {}", e);
+            }
+        }
+        if let Some(port) = self.gdb_port {
+            match GdbStub::listen(port) {
+                Ok(gdb) => {
+                    self.gdb = Some(gdb);
+                    self.pause();
+                }
+                Err(e) => println!("Failed to start GDB server: {}", e),
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        self.stop_recording()
+            .unwrap_or_else(|e| println!("Failed to finish recording: {}", e));
+        if self.profiler.enabled {
+            if let Err(e) = self.stop_profiling() {
+                println!("Failed to save profile: {}", e);
+            }
+        }
+        if self.dump_vram_on_exit {
+            if let Err(e) = self.dump_vram() {
+                println!("Failed to dump vram: {}", e);
+            }
+        }
+        if let Some(tracer) = self.cpu.tracer.as_mut() {
+            tracer
+                .flush()
+                .unwrap_or_else(|e| println!("Failed to write trace: {}", e));
+        }
+    }
+
+    /// Runs the given number of frames without a window, e.g. to record a video on CI.
+    /// The debugger takes commands from the console whenever it is paused.
+    pub fn run_headless(&mut self, frames: u64) {
+        self.start();
+        let end = self.frame_count().saturating_add(frames);
+        while self.frame_count() < end {
+            if self.debugger.paused {
+                if !self.debug_session() {
+                    break;
+                }
+            } else {
+                self.run_until_frame();
+                self.poll_gdb();
+            }
+        }
+        self.stop();
+    }
+
+    pub fn run(&mut self) {
+        self.start();
 
+        let (width, height) = self.frame_size();
         let mut event_loop = EventLoop::new();
         let config = ConfigBuilder::default()
             .window_title("Gameboy Emulator".to_string())
-            .buffer_size(Some(LogicalSize::new(
-                SCREEN_WIDTH as u32,
-                SCREEN_HEIGHT as u32,
-            )))
+            .buffer_size(Some(LogicalSize::new(width as u32, height as u32)))
             .resizable(true)
             .invert_y(false)
             .build();
-        let mut window = get_fancy(config, &event_loop);
+        let mut window = get_fancy(config, &event_loop).glutin_breakout();
+        let mut viewers: Vec<ViewerWindow> = Vec::new();
 
         let key_mapping = vec![
             (Key::Up, joypad::KEY_UP),
@@ -53,23 +174,119 @@ impl Emulator {
             (Key::Return, joypad::KEY_START),
             (Key::Space, joypad::KEY_SELECT),
         ];
+        let viewer_keys = [Key::Key1, Key::Key2, Key::Key3];
 
+        let mut input = BasicInput::default();
+        let mut buffer_size = (width, height);
         let mut previous = std::time::Instant::now();
         let mut last_speed_change = std::time::Instant::now();
 
-        window.glutin_handle_basic_input(&mut event_loop, |fb, input| {
+        event_loop.run_return(|event, target, flow| {
+            *flow = ControlFlow::Poll;
+            match event {
+                Event::WindowEvent { window_id, event } => {
+                    let main_id = window.context.window().id();
+                    match event {
+                        // keys work in every window, so the viewers can be changed while they have focus
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    virtual_keycode: Some(key),
+                                    state,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            input.keys.entry(key).or_default().1 = state == ElementState::Pressed;
+                        }
+                        WindowEvent::CloseRequested if window_id == main_id => {
+                            *flow = ControlFlow::Exit;
+                        }
+                        WindowEvent::CloseRequested => {
+                            viewers.retain(|viewer| viewer.id() != window_id);
+                        }
+                        WindowEvent::Resized(size) => {
+                            let resized = std::iter::once(&mut window)
+                                .chain(viewers.iter_mut().map(|viewer| &mut viewer.window))
+                                .find(|window| window.context.window().id() == window_id);
+                            if let Some(resized) = resized {
+                                if make_current(resized) {
+                                    resized.fb.resize_viewport(size.width, size.height);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    return;
+                }
+                // the emulator runs once all pending events are handled
+                Event::MainEventsCleared => {}
+                _ => return,
+            }
+
             let now = std::time::Instant::now();
             let elapsed = now.duration_since(previous);
             previous = now;
 
             if input.key_is_down(Key::Escape) {
-                return false;
+                *flow = ControlFlow::Exit;
+                return;
             } else if input.key_pressed(Key::S) {
                 self.save_state()
                     .unwrap_or_else(|e| println!("Failed to save state: {}", e));
             } else if input.key_pressed(Key::L) {
                 self.load_save()
                     .unwrap_or_else(|e| println!("Failed to load state: {}", e));
+            } else if input.key_pressed(Key::P) {
+                self.cycle_palette();
+            } else if input.key_pressed(Key::C) {
+                self.set_colorization(!self.colorization);
+            } else if input.key_pressed(Key::F) {
+                self.filter.cycle_scaler();
+                println!("Filter: {:?}", self.filter.scaler);
+            } else if input.key_pressed(Key::G) {
+                self.filter.grid = !self.filter.grid;
+            } else if input.key_pressed(Key::H) {
+                self.filter.ghosting = !self.filter.ghosting;
+            } else if input.key_pressed(Key::R) {
+                let result = if self.recorder.is_some() {
+                    self.stop_recording()
+                } else {
+                    self.start_recording(None).map(|_| ())
+                };
+                result.unwrap_or_else(|e| println!("Failed to record: {}", e));
+            } else if input.key_pressed(Key::D) {
+                self.pause();
+            } else if input.key_pressed(Key::F9) {
+                if self.profiler.enabled {
+                    if let Err(e) = self.stop_profiling() {
+                        println!("Failed to save profile: {}", e);
+                    }
+                } else {
+                    self.start_profiling();
+                }
+            } else if let Some(index) = viewer_keys.iter().position(|key| input.key_pressed(*key)) {
+                // the number keys open and close the tile, tile map and sprite windows
+                let view = VIEWS[index];
+                if viewers.iter().any(|viewer| viewer.view == view) {
+                    viewers.retain(|viewer| viewer.view != view);
+                } else {
+                    let image = self.render_view(view);
+                    viewers.push(ViewerWindow::open(view, &image, target));
+                    if view == View::Sprites {
+                        print!("{}", sprite_table(&self.cpu.mmu.gpu));
+                    }
+                }
+            } else if input.key_pressed(Key::T) {
+                self.tile_palette = self.tile_palette.next();
+                println!("Tile palette: {:?}", self.tile_palette);
+            } else if input.key_pressed(Key::K) {
+                self.toggle_cheats();
+            } else if input.key_pressed(Key::F12) {
+                match self.screenshot(2) {
+                    Ok(path) => println!("Saved screenshot to {}", path),
+                    Err(e) => println!("Failed to save screenshot: {}", e),
+                }
             } else if input.key_is_down(Key::Comma) {
                 if self.speed < 1000 && now.duration_since(last_speed_change).as_millis() > 100 {
                     self.speed += 10;
@@ -91,23 +308,436 @@ impl Emulator {
                     self.cpu.mmu.joypad.on_key_released(*to);
                 }
             }
+            // the next events are compared against the keys as they are now
+            for (_, key) in input.keys.iter_mut() {
+                key.0 = key.1;
+            }
+
+            // the windows stand still while the debugger reads commands
+            self.poll_gdb();
+            if self.debugger.paused {
+                if !self.debug_session() {
+                    *flow = ControlFlow::Exit;
+                }
+                previous = std::time::Instant::now();
+                return;
+            }
 
             let ticks = elapsed.as_micros() * 4194304 / 1000000 * self.speed / 100;
             let mut cycles = 0;
 
             while cycles < ticks {
-                cycles += self.cpu.update() as u128;
+                if self.debugger.check(&mut self.cpu) {
+                    break;
+                }
+                cycles += self.step() as u128;
+            }
+
+            // only whole frames are presented
+            if self.cpu.mmu.gpu.frame_ready {
+                self.cpu.mmu.gpu.frame_ready = false;
+                let (width, height) = self.filter.output_size(width, height);
+                present(
+                    &mut window,
+                    &mut buffer_size,
+                    width,
+                    height,
+                    self.filtered_frame(),
+                );
+                for viewer in viewers.iter_mut() {
+                    let image = self.render_view(viewer.view);
+                    present(
+                        &mut viewer.window,
+                        &mut viewer.size,
+                        image.width,
+                        image.height,
+                        &image.pixels,
+                    );
+                }
+            }
+        });
+        self.stop();
+    }
+
+    fn step(&mut self) -> u16 {
+        if self.profiler.enabled {
+            self.profiler.sample(&self.cpu);
+        }
+        let cycles = self.cpu.update();
+        if self.profiler.enabled {
+            self.profiler.record(cycles);
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.capture(self.cpu.mmu.gpu.frame_count, current_frame(&self.cpu))
+            {
+                println!("Failed to record frame: {}", e);
+                self.recorder = None;
+            }
+        }
+        cycles
+    }
+
+    /// Runs until the next frame is completed or a breakpoint is hit and returns the number of cycles executed.
+    pub fn run_until_frame(&mut self) -> u32 {
+        let mut cycles = 0;
+        self.cpu.mmu.gpu.frame_ready = false;
+        while !self.cpu.mmu.gpu.frame_ready {
+            if self.debugger.check(&mut self.cpu) {
+                break;
+            }
+            cycles += self.step() as u32;
+        }
+        self.cpu.mmu.gpu.frame_ready = false;
+        cycles
+    }
+
+    /// The last completed frame, including the border in SGB mode.
+    pub fn frame(&self) -> &[u32] {
+        current_frame(&self.cpu)
+    }
+
+    /// The last completed frame after scaling and post-processing, see `Filter::output_size`.
+    pub fn filtered_frame(&mut self) -> &[u32] {
+        let (width, height) = self.frame_size();
+        self.filter.apply(current_frame(&self.cpu), width, height)
+    }
+
+    pub fn frame_size(&self) -> (usize, usize) {
+        match &self.cpu.mmu.joypad.sgb {
+            Some(_) => (SGB_WIDTH, SGB_HEIGHT),
+            None => (SCREEN_WIDTH, SCREEN_HEIGHT),
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.cpu.mmu.gpu.frame_count
+    }
+
+    /// Saves the last frame as `<TITLE>_<YYYYMMDD-HHMMSS>.png` and returns the path.
+    pub fn screenshot(&self, scale: usize) -> Result<String> {
+        let path = self.output_path(&self.screenshot_dir, "png")?;
+        let (width, height) = self.frame_size();
+        write(
+            &path,
+            framebuffer_to_png(self.frame(), width, height, scale),
+        )?;
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    /// The tiles, tile maps or sprites in VRAM.
+    pub fn render_view(&self, view: View) -> Image {
+        let gpu = &self.cpu.mmu.gpu;
+        match view {
+            View::Tiles => render_tiles(gpu, self.tile_palette),
+            View::Maps => render_maps(gpu),
+            View::Sprites => render_sprites(gpu),
+        }
+    }
+
+    /// Saves every VRAM view as a PNG and the sprite table as text, returns the paths.
+    pub fn dump_vram(&self) -> Result<Vec<String>> {
+        let mut paths = Vec::new();
+        for view in VIEWS {
+            let image = self.render_view(view);
+            let extension = format!("{:?}.png", view).to_lowercase();
+            let path = self.output_path(&self.vram_dir, &extension)?;
+            write(
+                &path,
+                framebuffer_to_png(&image.pixels, image.width, image.height, 1),
+            )?;
+            paths.push(path.to_string_lossy().to_string());
+        }
+        let path = self.output_path(&self.vram_dir, "sprites.txt")?;
+        write(&path, sprite_table(&self.cpu.mmu.gpu))?;
+        paths.push(path.to_string_lossy().to_string());
+        println!("Saved vram to {}", paths.join(", "));
+        Ok(paths)
+    }
+
+    /// Saves the VRAM views when the emulator exits, e.g. after a headless test run.
+    pub fn set_dump_vram(&mut self, enabled: bool) {
+        self.dump_vram_on_exit = enabled;
+    }
+
+    /// Loads the cheat list of the rom, changes to it are saved to the same file.
+    pub fn load_cheats(&mut self, path: &str) -> Result<()> {
+        self.cpu.mmu.cheats.load(path)?;
+        let count = self.cpu.mmu.cheats.list().len();
+        if count > 0 {
+            println!("Loaded {} cheats from {}", count, path);
+        }
+        Ok(())
+    }
+
+    pub fn cheats(&mut self) -> &mut Cheats {
+        &mut self.cpu.mmu.cheats
+    }
+
+    pub fn toggle_cheats(&mut self) {
+        let cheats = &mut self.cpu.mmu.cheats;
+        cheats.enabled = !cheats.enabled;
+        println!("Cheats {}", if cheats.enabled { "on" } else { "off" });
+    }
+
+    /// Sets an IPS, UPS or BPS patch to apply when the rom is loaded.
+    pub fn set_patch_path(&mut self, path: Option<&str>) {
+        self.patch_path = path.map(|path| path.to_string());
+    }
+
+    /// Sets a file to record to as soon as the emulator starts.
+    pub fn set_record_path(&mut self, path: &str) {
+        self.record_path = Some(path.to_string());
+    }
+
+    /// Starts recording every frame, by default to `<TITLE>_<YYYYMMDD-HHMMSS>.y4m`.
+    pub fn start_recording(&mut self, path: Option<&str>) -> Result<String> {
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => self
+                .output_path(&self.recording_dir, "apng")?
+                .to_string_lossy()
+                .to_string(),
+        };
+        let (width, height) = self.frame_size();
+        self.recorder = Some(Recorder::start(&path, width, height, self.frame_count())?);
+        println!("Recording to {}", path);
+        Ok(path)
+    }
+
+    pub fn stop_recording(&mut self) -> Result<()> {
+        if let Some(recorder) = self.recorder.take() {
+            println!("Recorded {} frames to {}", recorder.frames, recorder.path);
+            recorder.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Counts the cycles spent per address, function and frame from now on.
+    pub fn start_profiling(&mut self) {
+        self.profiler.reset();
+        self.profiler.enabled = true;
+        println!("Profiling");
+    }
+
+    /// Saves a report as `<TITLE>_<YYYYMMDD-HHMMSS>.txt` and the call stacks as `.folded` for flame graphs.
+    pub fn stop_profiling(&mut self) -> Result<String> {
+        self.profiler.enabled = false;
+        let path = self.output_path(&self.profile_dir, "txt")?;
+        let symbols = &self.debugger.symbols;
+        write(&path, self.profiler.report(symbols))?;
+        write(
+            path.with_extension("folded"),
+            self.profiler.collapsed_stacks(symbols),
+        )?;
+        let path = path.to_string_lossy().to_string();
+        println!("Saved profile to {}", path);
+        Ok(path)
+    }
+
+    // a new file named after the game and the current time
+    fn output_path(&self, folder: &str, extension: &str) -> Result<PathBuf> {
+        let name = match self.cpu.mmu.cartrige.as_deref() {
+            Some(cartridge) => title(cartridge)
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect(),
+            None => String::new(),
+        };
+        let name = if name.is_empty() { "GAMEBOY" } else { &name };
+
+        let folder = Path::new(folder);
+        create_dir_all(folder)?;
+        let stamp = timestamp();
+        let mut path = folder.join(format!("{}_{}.{}", name, stamp, extension));
+        // several files within a second get a counter
+        let mut index = 2;
+        while path.exists() {
+            path = folder.join(format!("{}_{}_{}.{}", name, stamp, index, extension));
+            index += 1;
+        }
+        Ok(path)
+    }
+
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Read, write and execute watchpoints, with callbacks for their hits.
+    pub fn watchpoints(&mut self) -> &mut Watchpoints {
+        &mut self.cpu.mmu.watchpoints
+    }
+
+    /// Stops before the next instruction and hands control to the debugger.
+    pub fn pause(&mut self) {
+        self.debugger.paused = true;
+    }
+
+    /// Runs a debugger command, see `debugger::HELP`. Returns false when the emulator should exit.
+    pub fn debug_command(&mut self, line: &str) -> bool {
+        match self.debugger.execute(&mut self.cpu, line) {
+            Action::None | Action::Continue => {}
+            Action::Step(count) => {
+                for _ in 0..count {
+                    self.step();
+                    if self.debugger.watch_hit(&mut self.cpu) {
+                        break;
+                    }
+                }
+                println!(
+                    "{}",
+                    self.debugger.disassemble(&self.cpu, self.cpu.pc, 1)[0]
+                );
+            }
+            Action::Frame => {
+                self.run_until_frame();
+                println!(
+                    "{}",
+                    self.debugger.disassemble(&self.cpu, self.cpu.pc, 1)[0]
+                );
+            }
+            Action::Quit => return false,
+        }
+        true
+    }
+
+    /// Waits for a GDB connection on the given port when the emulator starts.
+    pub fn set_gdb_port(&mut self, port: u16) {
+        self.gdb_port = Some(port);
+    }
+
+    // commands come from GDB when it is connected, otherwise from the console
+    fn debug_session(&mut self) -> bool {
+        let Some(mut gdb) = self.gdb.take() else {
+            return self.debug_console();
+        };
+        match gdb.serve(&mut self.cpu, &mut self.debugger) {
+            Ok(Resume::Continue) => self.debugger.resume(),
+            Ok(Resume::Step) => {
+                self.step();
+                self.debugger.watch_hit(&mut self.cpu);
+            }
+            Ok(Resume::Detach) => {
+                self.debugger.resume();
+                return true;
+            }
+            Ok(Resume::Kill) => return false,
+            Err(e) => {
+                println!("GDB disconnected: {}", e);
+                self.debugger.resume();
+                return true;
+            }
+        }
+        self.gdb = Some(gdb);
+        true
+    }
+
+    fn poll_gdb(&mut self) {
+        if self.gdb.as_mut().is_some_and(|gdb| gdb.interrupted()) {
+            self.pause();
+        }
+    }
+
+    // reads commands from the console until execution continues
+    fn debug_console(&mut self) -> bool {
+        println!(
+            "{}",
+            self.debugger.disassemble(&self.cpu, self.cpu.pc, 1)[0]
+        );
+        let stdin = std::io::stdin();
+        while self.debugger.paused {
+            print!("(debug) ");
+            std::io::stdout().flush().unwrap_or_default();
+            let mut line = String::new();
+            // the end of the input quits, e.g. when commands are piped in
+            if stdin.read_line(&mut line).unwrap_or(0) == 0 || !self.debug_command(&line) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Traces every instruction executed from now on, `None` stops tracing.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.cpu.tracer = tracer;
+        if let Some(tracer) = self.cpu.tracer.as_mut() {
+            tracer.symbols = self.debugger.symbols.clone();
+        }
+    }
+
+    /// Shows labels from a symbol file in the debugger and traces.
+    pub fn load_symbols(&mut self, path: &str) -> Result<()> {
+        let symbols = Symbols::load(path)?;
+        println!("Loaded {} symbols from {}", symbols.len(), path);
+        if let Some(tracer) = self.cpu.tracer.as_mut() {
+            tracer.symbols = symbols.clone();
+        }
+        self.debugger.symbols = symbols;
+        Ok(())
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.cpu.tracer.as_ref()
+    }
+
+    pub fn attach_printer(&mut self, output_dir: &str) {
+        self.cpu.mmu.serial.printer = Some(Printer::new(output_dir));
+    }
+
+    pub fn printed_pages(&self) -> &[PrintedPage] {
+        match &self.cpu.mmu.serial.printer {
+            Some(printer) => printer.pages(),
+            None => &[],
+        }
+    }
+
+    pub fn palettes(&self) -> &[Palette] {
+        &self.palettes
+    }
+
+    pub fn set_palette(&mut self, index: usize) {
+        self.palette_index = index % self.palettes.len();
+        self.cpu.mmu.gpu.colors = self.palettes[self.palette_index].clone();
+    }
+
+    pub fn cycle_palette(&mut self) {
+        self.set_palette(self.palette_index + 1);
+        println!("Palette: {}", self.palettes[self.palette_index].name);
+    }
+
+    /// Colors DMG games like the CGB boot ROM does, overriding the selected palette.
+    pub fn set_colorization(&mut self, enabled: bool) {
+        self.colorization = enabled;
+        match self.cpu.mmu.cartrige.as_deref() {
+            Some(cartridge) if enabled => {
+                let palette = colorize(cartridge);
+                println!("Palette: {}", palette.name);
+                self.cpu.mmu.gpu.colors = palette;
             }
+            _ => self.set_palette(self.palette_index),
+        }
+    }
 
-            fb.update_buffer(&self.cpu.mmu.gpu.video_buffer);
+    /// Runs games with SGB support as on a Super Game Boy. Takes effect when the rom is loaded.
+    pub fn set_sgb_mode(&mut self, enabled: bool) {
+        self.sgb_mode = enabled;
+    }
 
-            true
-        })
+    /// Adds the custom palettes from the palette file to the presets.
+    pub fn load_palettes(&mut self) -> Result<()> {
+        let palettes = load_palettes(&self.palette_path)?;
+        self.palettes.extend(palettes);
+        Ok(())
     }
 
     pub fn load_rom(&mut self) -> Result<()> {
-        let rom = load_rom(&self.rom_path)?;
+        let rom = load_rom(&self.rom_path, self.patch_path.as_deref())?;
+        self.cpu.mmu.joypad.sgb = if self.sgb_mode && supports_sgb(rom.as_ref()) {
+            Some(SGB::new())
+        } else {
+            None
+        };
         self.cpu.mmu.cartrige = Some(rom);
+        self.set_colorization(self.colorization);
         Ok(())
     }
 
@@ -119,3 +749,98 @@ impl Emulator {
         save_state(self.cpu.mmu.cartrige.as_ref().unwrap(), &self.ram_path)
     }
 }
+
+// A VRAM view in a window of its own next to the game
+struct ViewerWindow {
+    view: View,
+    window: GlutinBreakout,
+    size: (usize, usize), // of the buffer
+}
+
+impl ViewerWindow {
+    fn open<T>(view: View, image: &Image, target: &EventLoopWindowTarget<T>) -> ViewerWindow {
+        let (width, height) = (image.width as u32, image.height as u32);
+        let config = ConfigBuilder::default()
+            .window_title(format!("{:?}", view))
+            .window_size(LogicalSize::new(width as f64 * 2.0, height as f64 * 2.0))
+            .buffer_size(Some(LogicalSize::new(width, height)))
+            .resizable(true)
+            .invert_y(false)
+            .build();
+        ViewerWindow {
+            view,
+            window: get_fancy(config, target).glutin_breakout(),
+            size: (image.width, image.height),
+        }
+    }
+
+    fn id(&self) -> WindowId {
+        self.window.context.window().id()
+    }
+}
+
+// Every window has its own OpenGL context, which has to be current to draw into the window.
+fn make_current(window: &mut GlutinBreakout) -> bool {
+    // the context is only used on this thread
+    unsafe { window.make_current() }
+        .map_err(|e| println!("Failed to switch window: {}", e))
+        .is_ok()
+}
+
+fn present(
+    window: &mut GlutinBreakout,
+    size: &mut (usize, usize),
+    width: usize,
+    height: usize,
+    pixels: &[u32],
+) {
+    if !make_current(window) {
+        return;
+    }
+    if *size != (width, height) {
+        *size = (width, height);
+        window.fb.resize_buffer(width as u32, height as u32);
+    }
+    window.fb.update_buffer(pixels);
+    window
+        .context
+        .swap_buffers()
+        .unwrap_or_else(|e| println!("Failed to draw window: {}", e));
+}
+
+fn current_frame(cpu: &CPU) -> &[u32] {
+    match &cpu.mmu.joypad.sgb {
+        Some(sgb) => &sgb.frame_buffer,
+        None => &cpu.mmu.gpu.frame_buffer,
+    }
+}
+
+// current UTC time as YYYYMMDD-HHMMSS
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
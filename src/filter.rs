@@ -0,0 +1,399 @@
+// Filters applied to 0x00RRGGBB frames before they are presented
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Scaler {
+    Nearest(usize),
+    Scale2x,
+    Scale3x,
+    Hq2x,
+    Xbr2x,
+}
+
+const SCALERS: [Scaler; 7] = [
+    Scaler::Nearest(1),
+    Scaler::Nearest(2),
+    Scaler::Nearest(3),
+    Scaler::Scale2x,
+    Scaler::Scale3x,
+    Scaler::Hq2x,
+    Scaler::Xbr2x,
+];
+
+pub struct Filter {
+    pub scaler: Scaler,
+    pub grid: bool,     // darkens the edges of every pixel like the gaps of the LCD
+    pub ghosting: bool, // blends with the previous frame like the slow LCD does
+    previous: Vec<u32>,
+    output: Vec<u32>,
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter {
+            scaler: Scaler::Nearest(1),
+            grid: false,
+            ghosting: false,
+            previous: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn cycle_scaler(&mut self) {
+        let index = SCALERS.iter().position(|s| *s == self.scaler);
+        self.scaler = SCALERS[index.map_or(0, |i| (i + 1) % SCALERS.len())];
+    }
+
+    pub fn scale(&self) -> usize {
+        match self.scaler {
+            Scaler::Nearest(scale) => scale.max(1),
+            Scaler::Scale2x | Scaler::Hq2x | Scaler::Xbr2x => 2,
+            Scaler::Scale3x => 3,
+        }
+    }
+
+    pub fn output_size(&self, width: usize, height: usize) -> (usize, usize) {
+        (width * self.scale(), height * self.scale())
+    }
+
+    /// Filters a frame. Should be called once per frame, as ghosting keeps the previous one.
+    pub fn apply(&mut self, frame: &[u32], width: usize, height: usize) -> &[u32] {
+        // keeping the blended frame lets older frames fade out gradually
+        if self.ghosting && self.previous.len() == frame.len() {
+            for (previous, color) in self.previous.iter_mut().zip(frame) {
+                *previous = blend(*color, *previous, 1, 1);
+            }
+        } else {
+            self.previous.clear();
+            self.previous.extend_from_slice(frame);
+        }
+
+        let scale = self.scale();
+        let (out_width, out_height) = self.output_size(width, height);
+        self.output.clear();
+        self.output.resize(out_width * out_height, 0);
+        let image = Image {
+            pixels: &self.previous,
+            width,
+            height,
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut block = Block {
+                    output: &mut self.output,
+                    start: y * scale * out_width + x * scale,
+                    stride: out_width,
+                    scale,
+                    grid: self.grid && scale > 1,
+                };
+                match self.scaler {
+                    Scaler::Nearest(_) => block.fill(image.get(x, y, 0, 0)),
+                    Scaler::Scale2x => scale2x(&image, x, y, &mut block),
+                    Scaler::Scale3x => scale3x(&image, x, y, &mut block),
+                    Scaler::Hq2x => hq2x(&image, x, y, &mut block),
+                    Scaler::Xbr2x => xbr2x(&image, x, y, &mut block),
+                }
+            }
+        }
+
+        &self.output
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Image<'a> {
+    pixels: &'a [u32],
+    width: usize,
+    height: usize,
+}
+
+impl Image<'_> {
+    // pixels outside of the image repeat the closest edge pixel
+    fn get(&self, x: usize, y: usize, dx: i32, dy: i32) -> u32 {
+        let x = (x as i32 + dx).clamp(0, self.width as i32 - 1) as usize;
+        let y = (y as i32 + dy).clamp(0, self.height as i32 - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+}
+
+// the square of output pixels scaled from one source pixel
+struct Block<'a> {
+    output: &'a mut [u32],
+    start: usize, // of the top left pixel
+    stride: usize,
+    scale: usize,
+    grid: bool,
+}
+
+impl Block<'_> {
+    // pixels are numbered row by row from the top left
+    fn set(&mut self, index: usize, color: u32) {
+        let (dx, dy) = (index % self.scale, index / self.scale);
+        let on_grid = self.grid && (dx == self.scale - 1 || dy == self.scale - 1);
+        let color = if on_grid {
+            blend(color, 0, 3, 1)
+        } else {
+            color
+        };
+        self.output[self.start + dy * self.stride + dx] = color;
+    }
+
+    fn fill(&mut self, color: u32) {
+        for index in 0..self.scale * self.scale {
+            self.set(index, color);
+        }
+    }
+}
+
+// https://www.scale2x.it/algorithm
+fn scale2x(image: &Image, x: usize, y: usize, block: &mut Block) {
+    let b = image.get(x, y, 0, -1);
+    let d = image.get(x, y, -1, 0);
+    let e = image.get(x, y, 0, 0);
+    let f = image.get(x, y, 1, 0);
+    let h = image.get(x, y, 0, 1);
+
+    if b == h || d == f {
+        return block.fill(e);
+    }
+    block.set(0, if d == b { d } else { e });
+    block.set(1, if b == f { f } else { e });
+    block.set(2, if d == h { d } else { e });
+    block.set(3, if h == f { f } else { e });
+}
+
+fn scale3x(image: &Image, x: usize, y: usize, block: &mut Block) {
+    let a = image.get(x, y, -1, -1);
+    let b = image.get(x, y, 0, -1);
+    let c = image.get(x, y, 1, -1);
+    let d = image.get(x, y, -1, 0);
+    let e = image.get(x, y, 0, 0);
+    let f = image.get(x, y, 1, 0);
+    let g = image.get(x, y, -1, 1);
+    let h = image.get(x, y, 0, 1);
+    let i = image.get(x, y, 1, 1);
+
+    if b == h || d == f {
+        return block.fill(e);
+    }
+    let top = (d == b && e != c) || (b == f && e != a);
+    let left = (d == b && e != g) || (d == h && e != a);
+    let right = (b == f && e != i) || (h == f && e != c);
+    let bottom = (d == h && e != i) || (h == f && e != g);
+    block.set(0, if d == b { d } else { e });
+    block.set(1, if top { b } else { e });
+    block.set(2, if b == f { f } else { e });
+    block.set(3, if left { d } else { e });
+    block.set(4, e);
+    block.set(5, if right { f } else { e });
+    block.set(6, if d == h { d } else { e });
+    block.set(7, if bottom { h } else { e });
+    block.set(8, if h == f { f } else { e });
+}
+
+// Interpolates like hq2x on edges found with its YUV thresholds, without the full pattern table.
+fn hq2x(image: &Image, x: usize, y: usize, block: &mut Block) {
+    let e = image.get(x, y, 0, 0);
+    for (i, (dx, dy)) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].into_iter().enumerate() {
+        let corner = image.get(x, y, dx, dy);
+        let vertical = image.get(x, y, 0, dy);
+        let horizontal = image.get(x, y, dx, 0);
+
+        let color = if similar(vertical, horizontal) && !similar(e, vertical) {
+            if similar(corner, vertical) || !similar(e, corner) {
+                blend(e, blend(vertical, horizontal, 1, 1), 1, 1)
+            } else {
+                blend(e, blend(vertical, horizontal, 1, 1), 3, 1)
+            }
+        } else if !similar(e, corner) && similar(e, vertical) && similar(e, horizontal) {
+            blend(e, corner, 7, 1)
+        } else {
+            e
+        };
+        block.set(i, color);
+    }
+}
+
+// Level 1 of xBR: compares the color gradients along both diagonals of each corner.
+fn xbr2x(image: &Image, x: usize, y: usize, block: &mut Block) {
+    let e = image.get(x, y, 0, 0);
+    block.fill(e);
+
+    // the rules are written for the bottom right corner, the others are rotations of it
+    for (dx, dy) in [(1, 1), (-1, 1), (-1, -1), (1, -1)] {
+        let p = |u: i32, v: i32| {
+            // rotates (u, v) from the bottom right corner to the current one
+            let (rx, ry) = match (dx, dy) {
+                (1, 1) => (u, v),
+                (-1, 1) => (-v, u),
+                (-1, -1) => (-u, -v),
+                _ => (v, -u),
+            };
+            image.get(x, y, rx, ry)
+        };
+
+        let (b, c, d) = (p(0, -1), p(1, -1), p(-1, 0));
+        let (f, g, h, i) = (p(1, 0), p(-1, 1), p(0, 1), p(1, 1));
+        let (f4, h5, i4, i5) = (p(2, 0), p(0, 2), p(2, 1), p(1, 2));
+
+        let across = distance(e, c)
+            + distance(e, g)
+            + distance(i, f4)
+            + distance(i, h5)
+            + 4 * distance(h, f);
+        let along = distance(h, d)
+            + distance(h, i5)
+            + distance(f, i4)
+            + distance(f, b)
+            + 4 * distance(e, i);
+
+        if across < along && e != f && e != h {
+            let color = if distance(e, f) <= distance(e, h) {
+                f
+            } else {
+                h
+            };
+            let index = (dy + 1) as usize / 2 * 2 + (dx + 1) as usize / 2;
+            block.set(index, blend(e, color, 1, 1));
+        }
+    }
+}
+
+fn yuv(color: u32) -> (i32, i32, i32) {
+    let r = ((color >> 16) & 0xFF) as i32;
+    let g = ((color >> 8) & 0xFF) as i32;
+    let b = (color & 0xFF) as i32;
+    (
+        (r + g + b) >> 2,
+        128 + ((r - b) >> 2),
+        128 + ((2 * g - r - b) >> 3),
+    )
+}
+
+fn similar(a: u32, b: u32) -> bool {
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+    (ya - yb).abs() <= 0x30 && (ua - ub).abs() <= 7 && (va - vb).abs() <= 6
+}
+
+fn distance(a: u32, b: u32) -> i32 {
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+    48 * (ya - yb).abs() + 7 * (ua - ub).abs() + 6 * (va - vb).abs()
+}
+
+// mixes two colors with the given weights
+fn blend(a: u32, b: u32, weight_a: u32, weight_b: u32) -> u32 {
+    let total = weight_a + weight_b;
+    let channel = |shift: u32| {
+        let mixed = (((a >> shift) & 0xFF) * weight_a + ((b >> shift) & 0xFF) * weight_b) / total;
+        mixed << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::encode_png;
+    use std::fs::{create_dir_all, read, write};
+
+    const WIDTH: usize = 24;
+    const HEIGHT: usize = 16;
+    const SHADES: [u32; 4] = [0x00E0F8D0, 0x0088C070, 0x00346856, 0x00081820];
+
+    // diagonals, a disc and a checkerboard, with the edges the scalers look for
+    fn frame() -> Vec<u32> {
+        let mut pixels = vec![SHADES[0]; WIDTH * HEIGHT];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let (dx, dy) = (x as i32 - 17, y as i32 - 8);
+                pixels[y * WIDTH + x] = if x == y || x + y == 14 {
+                    SHADES[3]
+                } else if dx * dx + dy * dy <= 20 {
+                    SHADES[2]
+                } else if x < 8 && y >= 12 && (x + y) % 2 == 0 {
+                    SHADES[1]
+                } else {
+                    SHADES[0]
+                };
+            }
+        }
+        pixels
+    }
+
+    // set UPDATE_GOLDEN=1 to write the expected images after an intended change
+    fn check(scaler: Scaler, grid: bool, name: &str) {
+        let mut filter = Filter::new();
+        filter.scaler = scaler;
+        filter.grid = grid;
+        let output = filter.apply(&frame(), WIDTH, HEIGHT).to_vec();
+        let (width, height) = filter.output_size(WIDTH, HEIGHT);
+        let png = encode_png(width, height, &output);
+
+        let folder = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+        let path = format!("{}/{}.png", folder, name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            create_dir_all(folder).unwrap();
+            write(&path, &png).unwrap();
+        }
+        let expected = read(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        assert!(png == expected, "{} differs from {}", name, path);
+    }
+
+    #[test]
+    fn ghosting() {
+        let mut filter = Filter::new();
+        filter.ghosting = true;
+        let black_white = [0x000000, 0xFFFFFF, 0x204060];
+        let white_black = [0xFFFFFF, 0x000000, 0x604020];
+
+        assert_eq!(filter.apply(&black_white, 3, 1), black_white);
+        assert_eq!(
+            filter.apply(&white_black, 3, 1),
+            [0x7F7F7F, 0x7F7F7F, 0x404040]
+        );
+        // older frames keep fading out
+        assert_eq!(
+            filter.apply(&white_black, 3, 1),
+            [0xBFBFBF, 0x3F3F3F, 0x504030]
+        );
+        // a frame of another size starts over
+        assert_eq!(filter.apply(&black_white[..2], 2, 1), &black_white[..2]);
+    }
+
+    #[test]
+    fn nearest() {
+        check(Scaler::Nearest(3), false, "nearest3x");
+    }
+
+    #[test]
+    fn nearest_grid() {
+        check(Scaler::Nearest(3), true, "nearest3x_grid");
+    }
+
+    #[test]
+    fn scale2x() {
+        check(Scaler::Scale2x, false, "scale2x");
+    }
+
+    #[test]
+    fn scale3x() {
+        check(Scaler::Scale3x, false, "scale3x");
+    }
+
+    #[test]
+    fn hq2x() {
+        check(Scaler::Hq2x, false, "hq2x");
+    }
+
+    #[test]
+    fn xbr2x() {
+        check(Scaler::Xbr2x, false, "xbr2x");
+    }
+}
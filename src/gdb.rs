@@ -0,0 +1,453 @@
+use crate::cpu::CPU;
+use crate::debugger::{Breakpoint, Debugger};
+use crate::traits::Register;
+use crate::watchpoint::{Access, Watchpoint};
+use std::fmt::Write as _;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{TcpListener, TcpStream};
+
+// https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+// Registers use the layout of gdb's z80 target: AF BC DE HL SP PC IX IY AF' BC' DE' HL' IR,
+// the registers the SM83 doesn't have read as zero.
+
+const REGISTER_COUNT: usize = 13;
+const MAX_MEMORY_READ: usize = 0x1000;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// What the emulator should do after the debugger sent its commands.
+pub enum Resume {
+    Continue,
+    Step,
+    Detach,
+    Kill,
+}
+
+/// A GDB remote serial protocol server for one debugger connection.
+pub struct GdbStub {
+    stream: TcpStream,
+    running: bool, // a stop reply is owed to the debugger
+    interrupted: bool,
+    breakpoints: Vec<Breakpoint>, // set by GDB, the console's breakpoints are left alone
+}
+
+impl GdbStub {
+    /// Waits for a debugger to connect on a local port.
+    pub fn listen(port: u16) -> Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("Waiting for GDB on port {}", port);
+        GdbStub::accept(listener)
+    }
+
+    fn accept(listener: TcpListener) -> Result<GdbStub> {
+        let (stream, address) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        println!("GDB connected from {}", address);
+        Ok(GdbStub {
+            stream,
+            running: false,
+            interrupted: false,
+            breakpoints: Vec::new(),
+        })
+    }
+
+    /// Checks without blocking whether the debugger sent an interrupt (Ctrl-C) while running.
+    pub fn interrupted(&mut self) -> bool {
+        let mut byte = [0];
+        self.stream.set_nonblocking(true).unwrap_or_default();
+        let read = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false).unwrap_or_default();
+        if matches!(read, Ok(1)) && byte[0] == 0x03 {
+            self.interrupted = true;
+        }
+        self.interrupted
+    }
+
+    /// Answers packets while the emulator is paused, until the debugger resumes execution.
+    pub fn serve(&mut self, cpu: &mut CPU, debugger: &mut Debugger) -> Result<Resume> {
+        if self.running {
+            self.running = false;
+            let reply = self.stop_reply(debugger);
+            self.send(&reply)?;
+        }
+
+        loop {
+            let packet = self.receive()?;
+            match self.handle(cpu, debugger, &packet) {
+                Ok(Some(resume)) => return Ok(resume),
+                Ok(None) => {}
+                Err(_) => self.send("E01")?,
+            }
+        }
+    }
+
+    // answers a packet, or returns how to resume
+    fn handle(
+        &mut self,
+        cpu: &mut CPU,
+        debugger: &mut Debugger,
+        packet: &str,
+    ) -> Result<Option<Resume>> {
+        // the command is a single character, packets may hold anything from the network
+        let command_length = packet.chars().next().map_or(0, char::len_utf8);
+        let (command, args) = packet.split_at(command_length);
+        let reply = match command {
+            "?" => self.stop_reply(debugger),
+            "g" => (0..REGISTER_COUNT)
+                .map(|index| hex(&read_register(cpu, index).to_le_bytes()))
+                .collect(),
+            "G" => {
+                let bytes = unhex(args)?;
+                for (index, value) in bytes.chunks_exact(2).enumerate() {
+                    write_register(cpu, index, u16::from_le_bytes([value[0], value[1]]));
+                }
+                "OK".to_string()
+            }
+            "p" => {
+                let index = parse_hex(args)? as usize;
+                hex(&read_register(cpu, index).to_le_bytes())
+            }
+            "P" => {
+                let (index, value) = split(args, '=')?;
+                let value = unhex(value)?;
+                let value = u16::from_le_bytes([
+                    value.first().copied().unwrap_or(0),
+                    value.get(1).copied().unwrap_or(0),
+                ]);
+                write_register(cpu, parse_hex(index)? as usize, value);
+                "OK".to_string()
+            }
+            "m" => {
+                let (address, length) = split(args, ',')?;
+                let address = parse_hex(address)? as u16;
+                let length = (parse_hex(length)? as usize).min(MAX_MEMORY_READ);
+                let memory = (0..length)
+                    .map(|i| cpu.mmu.peek(address.wrapping_add(i as u16)))
+                    .collect::<Vec<u8>>();
+                hex(&memory)
+            }
+            "M" => {
+                let (location, data) = split(args, ':')?;
+                let (address, _) = split(location, ',')?;
+                let address = parse_hex(address)? as u16;
+                for (offset, value) in unhex(data)?.into_iter().enumerate() {
+                    cpu.mmu.poke(address.wrapping_add(offset as u16), value);
+                }
+                "OK".to_string()
+            }
+            "c" | "s" => {
+                if !args.is_empty() {
+                    cpu.pc = parse_hex(args)? as u16;
+                }
+                self.running = true;
+                self.interrupted = false;
+                return Ok(Some(if command == "c" {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                }));
+            }
+            "Z" | "z" => self.set_point(cpu, debugger, command == "Z", args)?,
+            "D" => {
+                let owned = std::mem::take(&mut self.breakpoints);
+                debugger.breakpoints.retain(|b| !owned.contains(b));
+                self.send("OK")?;
+                return Ok(Some(Resume::Detach));
+            }
+            "k" => return Ok(Some(Resume::Kill)),
+            "H" => "OK".to_string(),
+            "q" if args.starts_with("Supported") => "PacketSize=4000".to_string(),
+            "q" if args == "Attached" => "1".to_string(),
+            // anything else is not supported
+            _ => String::new(),
+        };
+        self.send(&reply)?;
+        Ok(None)
+    }
+
+    fn stop_reply(&mut self, debugger: &mut Debugger) -> String {
+        if std::mem::take(&mut self.interrupted) {
+            return format!("S{:02x}", SIGINT);
+        }
+        match debugger.last_watch.take() {
+            Some(hit) => {
+                let kind = match hit.access {
+                    Access::Read => "rwatch",
+                    Access::Write => "watch",
+                    Access::Execute => return format!("S{:02x}", SIGTRAP),
+                };
+                format!("T{:02x}{}:{:04x};", SIGTRAP, kind, hit.address)
+            }
+            None => format!("S{:02x}", SIGTRAP),
+        }
+    }
+
+    // Z0 and Z1 are breakpoints, Z2 to Z4 write, read and access watchpoints
+    fn set_point(
+        &mut self,
+        cpu: &mut CPU,
+        debugger: &mut Debugger,
+        insert: bool,
+        args: &str,
+    ) -> Result<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next().unwrap_or_default();
+        let address = parse_hex(fields.next().unwrap_or_default())? as u16;
+        let length = parse_hex(fields.next().unwrap_or("1"))?.max(1) as u16;
+
+        let access = match kind {
+            "0" | "1" => {
+                let breakpoint = Breakpoint {
+                    bank: None,
+                    address,
+                };
+                let owned = self.breakpoints.contains(&breakpoint);
+                if insert && !debugger.breakpoints.contains(&breakpoint) {
+                    debugger.breakpoints.push(breakpoint);
+                    self.breakpoints.push(breakpoint);
+                } else if !insert && owned {
+                    debugger.breakpoints.retain(|b| *b != breakpoint);
+                    self.breakpoints.retain(|b| *b != breakpoint);
+                }
+                return Ok("OK".to_string());
+            }
+            "2" => "w",
+            "3" => "r",
+            "4" => "rw",
+            _ => return Ok(String::new()),
+        };
+
+        let watchpoint = Watchpoint::new(address..=address.wrapping_add(length - 1), access);
+        let watchpoints = &mut cpu.mmu.watchpoints;
+        if insert {
+            watchpoints.add(watchpoint);
+        } else if let Some(index) = watchpoints.list().iter().position(|w| {
+            w.range == watchpoint.range
+                && (w.read, w.write, w.execute)
+                    == (watchpoint.read, watchpoint.write, watchpoint.execute)
+        }) {
+            watchpoints.remove(index);
+        }
+        Ok("OK".to_string())
+    }
+
+    // $<data>#<checksum>, acknowledged with +
+    fn receive(&mut self) -> Result<String> {
+        loop {
+            // acknowledgements and interrupts while stopped are skipped
+            while self.read_byte()? != b'$' {}
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let expected = parse_hex(&String::from_utf8_lossy(&checksum)).unwrap_or(0x100);
+            if expected == checksum_of(&data) as u32 {
+                self.stream.write_all(b"+")?;
+                return Ok(String::from_utf8_lossy(&data).to_string());
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+fn read_register(cpu: &CPU, index: usize) -> u16 {
+    match index {
+        0 => u16::from_bytes(cpu.a, cpu.f),
+        1 => u16::from_bytes(cpu.b, cpu.c),
+        2 => u16::from_bytes(cpu.d, cpu.e),
+        3 => u16::from_bytes(cpu.h, cpu.l),
+        4 => cpu.sp,
+        5 => cpu.pc,
+        _ => 0,
+    }
+}
+
+fn write_register(cpu: &mut CPU, index: usize, value: u16) {
+    match index {
+        0 => (cpu.a, cpu.f) = (value.hi(), value.lo() & 0xF0),
+        1 => (cpu.b, cpu.c) = (value.hi(), value.lo()),
+        2 => (cpu.d, cpu.e) = (value.hi(), value.lo()),
+        3 => (cpu.h, cpu.l) = (value.hi(), value.lo()),
+        4 => cpu.sp = value,
+        5 => cpu.pc = value,
+        _ => {}
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut text, byte| {
+        write!(text, "{:02x}", byte).unwrap();
+        text
+    })
+}
+
+fn unhex(text: &str) -> Result<Vec<u8>> {
+    (0..text.len() / 2)
+        .map(|i| {
+            u8::from_str_radix(text.get(i * 2..i * 2 + 2).unwrap_or_default(), 16)
+                .map_err(|_| invalid(text))
+        })
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Result<u32> {
+    u32::from_str_radix(text, 16).map_err(|_| invalid(text))
+}
+
+fn split(text: &str, separator: char) -> Result<(&str, &str)> {
+    text.split_once(separator).ok_or_else(|| invalid(text))
+}
+
+fn invalid(packet: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid GDB packet: {}", packet),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::load_rom;
+    use std::thread;
+
+    // NOP, INC A, JR -4 at the entry point of an otherwise empty 32 KB rom
+    fn test_rom() -> String {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0x3C, 0x18, 0xFC]);
+        let path = std::env::temp_dir().join(format!("gb-emu-gdb-{}.gb", std::process::id()));
+        std::fs::write(&path, rom).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    // stands in for the emulator loop, with a console breakpoint at 0102
+    fn run_stub(listener: TcpListener) {
+        let mut cpu = CPU::new();
+        let rom = test_rom();
+        cpu.mmu.cartrige = Some(load_rom(&rom, None).unwrap());
+        std::fs::remove_file(rom).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.push(Breakpoint {
+            bank: None,
+            address: 0x0102,
+        });
+        let mut stub = GdbStub::accept(listener).unwrap();
+        loop {
+            match stub.serve(&mut cpu, &mut debugger).unwrap() {
+                Resume::Step => {
+                    cpu.update();
+                }
+                Resume::Continue => {
+                    debugger.resume();
+                    while !debugger.check(&mut cpu) {
+                        cpu.update();
+                    }
+                }
+                Resume::Detach | Resume::Kill => break,
+            }
+        }
+    }
+
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn connect(port: u16) -> Client {
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            Client { stream }
+        }
+
+        fn read_byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        fn send_raw(&mut self, packet: &str) {
+            self.stream.write_all(packet.as_bytes()).unwrap();
+        }
+
+        // sends a packet and returns the reply after checking acknowledgement and checksum
+        fn request(&mut self, data: &str) -> String {
+            self.send_raw(&format!("${}#{:02x}", data, checksum_of(data.as_bytes())));
+            assert_eq!(self.read_byte(), b'+');
+            assert_eq!(self.read_byte(), b'$');
+            let mut reply = Vec::new();
+            loop {
+                match self.read_byte() {
+                    b'#' => break,
+                    byte => reply.push(byte),
+                }
+            }
+            let checksum = [self.read_byte(), self.read_byte()];
+            assert_eq!(
+                u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(),
+                checksum_of(&reply)
+            );
+            self.send_raw("+");
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    #[test]
+    fn scripted_session() {
+        // the system picks a free port
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let stub = thread::spawn(move || run_stub(listener));
+        let mut client = Client::connect(port);
+
+        // a bad checksum is rejected and the packet can be sent again
+        client.send_raw("$?#00");
+        assert_eq!(client.read_byte(), b'-');
+        assert_eq!(client.request("?"), "S05");
+        // unknown packets, even ones that aren't ascii, get an empty reply
+        assert_eq!(client.request("\u{e9}t"), "");
+
+        // AF BC DE HL SP PC, little endian
+        let registers = client.request("g");
+        assert_eq!(registers.len(), REGISTER_COUNT * 4);
+        assert_eq!(&registers[16..24], "feff0001");
+
+        assert_eq!(client.request("m100,4"), "003c18fc");
+        assert_eq!(client.request("MC000,2:abcd"), "OK");
+        assert_eq!(client.request("mc000,2"), "abcd");
+
+        // GDB only removes its own breakpoints
+        assert_eq!(client.request("Z0,101,1"), "OK");
+        assert_eq!(client.request("z0,101,1"), "OK");
+        assert_eq!(client.request("Z0,102,1"), "OK");
+        assert_eq!(client.request("z0,102,1"), "OK");
+
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p5"), "0101");
+        // runs into the console breakpoint
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p5"), "0201");
+
+        client.send_raw(&format!("$k#{:02x}", checksum_of(b"k")));
+        assert_eq!(client.read_byte(), b'+');
+        stub.join().unwrap();
+    }
+}
@@ -1,4 +1,6 @@
+use crate::palette::{presets, Palette};
 use crate::traits::*;
+use std::collections::VecDeque;
 
 macro_rules! rgb {
     ($r:expr, $g:expr, $b:expr) => {
@@ -19,25 +21,80 @@ pub const MODE_VBLANK: u8 = 0b01;
 pub const MODE_OAM: u8 = 0b10;
 pub const MODE_VRAM: u8 = 0b11;
 
-type Tile = [[u8; 8]; 8];
+const SCANLINE_DOTS: u16 = 456;
+const FRAME_DOTS: u32 = 70224;
+const OAM_SCAN_DOTS: u16 = 80;
+const SPRITE_FETCH_DOTS: u8 = 6;
+const SPRITES_PER_LINE: usize = 10;
+
+pub type Tile = [[u8; 8]; 8];
+
+#[derive(Copy, Clone, PartialEq)]
+enum FetchStep {
+    Tile,
+    DataLo,
+    DataHi,
+    Push,
+}
+
+#[derive(Copy, Clone)]
+struct Fetcher {
+    step: FetchStep,
+    dots: u8,
+    window: bool,
+    first_fetch: bool,
+    tile_x: u8,
+    tile: u8,
+    row: u8,
+    data_lo: u8,
+    data_hi: u8,
+}
+
+impl Fetcher {
+    fn new(window: bool) -> Fetcher {
+        Fetcher {
+            step: FetchStep::Tile,
+            dots: 0,
+            window,
+            first_fetch: !window,
+            tile_x: 0,
+            tile: 0,
+            row: 0,
+            data_lo: 0,
+            data_hi: 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct SpritePixel {
+    color: u8,
+    palette: bool,
+    bg_priority: bool,
+}
 
 #[derive(Default, Copy, Clone)]
 pub struct Sprite {
-    y: u8,
-    x: u8,
-    tile_index: u8,
-    bg_priority: bool,
-    y_flip: bool,
-    x_flip: bool,
-    palette: bool,
+    pub y: u8, // screen position + 16
+    pub x: u8, // screen position + 8
+    pub tile_index: u8,
+    pub bg_priority: bool,
+    pub y_flip: bool,
+    pub x_flip: bool,
+    pub palette: bool,
 }
 
 pub struct GPU {
-    pub vram: [u8; 0x2000],    // 8KB of video ram
-    pub oam: [u8; 160],        // 160 bytes of sprite attribute memory
-    pub tiles: [Tile; 384],    // 384 tiles, each tile is 8x8 pixels
-    pub sprites: [Sprite; 40], // 40 sprites
-    pub video_buffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT],
+    pub vram: [u8; 0x2000],                                // 8KB of video ram
+    pub oam: [u8; 160],                                    // 160 bytes of sprite attribute memory
+    pub tiles: [Tile; 384],                                // 384 tiles, each tile is 8x8 pixels
+    pub sprites: [Sprite; 40],                             // 40 sprites
+    pub video_buffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT], // frame being drawn
+    pub frame_buffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT], // last completed frame
+    pub video_shades: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],  // shades 0-3 of the frame being drawn
+    pub frame_shades: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],  // shades of the last completed frame
+    pub frame_ready: bool,
+    pub frame_count: u64,
     pub cycles: u16,
     pub scanline_counter: u16,
     pub lcd_control: u8,
@@ -51,6 +108,26 @@ pub struct GPU {
     pub bg_palette: u8,
     pub obj_palette_0: u8,
     pub obj_palette_1: u8,
+    pub colors: Palette,
+    // pixel pipeline
+    pub lx: u8,
+    discard: u8,
+    fetcher: Fetcher,
+    bg_fifo: VecDeque<u8>,
+    sprite_fifo: VecDeque<SpritePixel>,
+    line_sprites: Vec<usize>,
+    sprite_fetch: Option<(usize, u8)>, // sprite index and dots spent fetching it
+    // window
+    window_line: u8, // internal line counter, only advances on lines showing the window
+    window_y_triggered: bool, // WY matched LY at some point during this frame
+    window_rendered: bool,
+    window_next_line: bool, // WX=166 shows the window on the whole following line
+    // lcd
+    stat_line: bool, // all enabled STAT sources OR'ed, interrupts fire on its rising edge
+    pending_interrupt: u8, // raised by register writes between updates
+    first_line: bool, // the first line after enabling the lcd skips the OAM scan
+    skip_frame: bool, // the first frame after enabling the lcd is not displayed
+    off_cycles: u32, // blank frames keep coming while the lcd is off
 }
 
 impl Memory for GPU {
@@ -59,7 +136,7 @@ impl Memory for GPU {
             0x8000..=0x9FFF => self.vram[address - 0x8000],
             0xFE00..=0xFE9F => self.oam[address - 0xFE00],
             0xFF40 => self.lcd_control,
-            0xFF41 => self.lcd_status,
+            0xFF41 => self.lcd_status | 0x80,
             0xFF42 => self.scroll_y,
             0xFF43 => self.scroll_x,
             0xFF44 => self.ly,
@@ -89,12 +166,30 @@ impl Memory for GPU {
                     self.update_sprite(address);
                 }
             }
-            0xFF40 => self.lcd_control = value,
-            0xFF41 => self.lcd_status = value,
+            0xFF40 => {
+                let was_enabled = self.lcd_enabled();
+                self.lcd_control = value;
+                if was_enabled && !self.lcd_enabled() {
+                    self.disable_lcd();
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.enable_lcd();
+                }
+            }
+            0xFF41 => {
+                // mode and coincidence flag are read only
+                self.lcd_status = (value & 0b0111_1000) | (self.lcd_status & 0b0000_0111);
+                self.pending_interrupt |= self.update_stat_line();
+            }
             0xFF42 => self.scroll_y = value,
             0xFF43 => self.scroll_x = value,
             0xFF44 => self.ly = 0,
-            0xFF45 => self.ly_compare = value,
+            0xFF45 => {
+                self.ly_compare = value;
+                if self.lcd_enabled() {
+                    self.compare_ly_lyc();
+                    self.pending_interrupt |= self.update_stat_line();
+                }
+            }
             0xFF47 => self.bg_palette = value,
             0xFF48 => self.obj_palette_0 = value,
             0xFF49 => self.obj_palette_1 = value,
@@ -113,6 +208,11 @@ impl GPU {
             sprites: [Sprite::default(); 40],
             tiles: [[[0; 8]; 8]; 384],
             video_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            video_shades: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_shades: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+            frame_count: 0,
             cycles: 0,
             scanline_counter: 0,
             lcd_control: 0x91,
@@ -124,98 +224,168 @@ impl GPU {
             bg_palette: 0xFC,
             obj_palette_0: 0xFF,
             obj_palette_1: 0xFF,
+            colors: presets().remove(0),
             window_y: 0x00,
             window_x: 0x00,
+            lx: 0,
+            discard: 0,
+            fetcher: Fetcher::new(false),
+            bg_fifo: VecDeque::with_capacity(16),
+            sprite_fifo: VecDeque::with_capacity(8),
+            line_sprites: Vec::with_capacity(40),
+            sprite_fetch: None,
+            window_line: 0,
+            window_y_triggered: false,
+            window_rendered: false,
+            window_next_line: false,
+            stat_line: false,
+            pending_interrupt: 0,
+            first_line: false,
+            skip_frame: false,
+            off_cycles: 0,
         }
     }
 
     pub fn update_graphics(&mut self, cycles: u16) -> u8 {
-        let mut needs_interrupt = 0;
+        let mut needs_interrupt = std::mem::take(&mut self.pending_interrupt);
 
         if !self.lcd_enabled() {
+            self.off_cycles += cycles as u32;
+            if self.off_cycles >= FRAME_DOTS {
+                self.off_cycles -= FRAME_DOTS;
+                self.publish_frame();
+            }
             return needs_interrupt;
         }
 
-        self.cycles += cycles;
+        for _ in 0..cycles {
+            needs_interrupt |= self.tick();
+        }
+
+        needs_interrupt
+    }
+
+    // advances the ppu by a single dot
+    fn tick(&mut self) -> u8 {
+        let mut needs_interrupt = 0;
 
         match self.mode() {
-            MODE_HBLANK => {
-                if self.cycles > 200 {
-                    self.cycles -= 200;
-                    self.ly += 1;
-                    if self.ly >= 144 {
-                        self.set_mode(MODE_VBLANK);
-                        needs_interrupt |= 1; // VBLANK interrupt
-                        if self.lcd_status.test_bit(4) {
-                            needs_interrupt |= 2; // LCD STAT interrupt
-                        }
-                    } else {
-                        self.set_mode(MODE_OAM);
-                        if self.lcd_status.test_bit(5) {
-                            needs_interrupt |= 2; // LCD STAT interrupt
-                        }
-                    }
-                    needs_interrupt |= self.compare_ly_lyc();
-                }
-            }
-            MODE_VBLANK => {
-                if self.cycles >= 456 {
-                    self.cycles %= 456;
-                    self.ly += 1;
-                    if self.ly == 154 {
-                        self.ly = 0;
-                        self.set_mode(MODE_OAM);
-                        if self.lcd_status.test_bit(5) {
-                            needs_interrupt |= 2; // LCD STAT interrupt
-                        }
-                    }
-                    needs_interrupt |= self.compare_ly_lyc();
-                }
-            }
             MODE_OAM => {
-                if self.cycles >= 80 {
-                    self.cycles %= 80;
+                if self.cycles == OAM_SCAN_DOTS {
+                    self.start_pixel_transfer();
                     self.set_mode(MODE_VRAM);
                 }
             }
             MODE_VRAM => {
-                if self.cycles >= 172 {
-                    self.cycles %= 172;
+                self.step_pixel_transfer();
+                if self.lx as usize == SCREEN_WIDTH {
+                    self.end_pixel_transfer();
                     self.set_mode(MODE_HBLANK);
-                    if self.lcd_status.test_bit(3) {
-                        needs_interrupt |= 2; // LCD STAT interrupt
-                    }
-                    self.render_scanline();
                 }
             }
+            MODE_HBLANK => {
+                if self.first_line && self.cycles == OAM_SCAN_DOTS {
+                    self.first_line = false;
+                    self.start_pixel_transfer();
+                    self.set_mode(MODE_VRAM);
+                }
+            }
+            MODE_VBLANK => (),
             _ => unreachable!(),
         }
 
-        needs_interrupt
-    }
-
-    fn get_color(palette: u8, color: u8) -> u32 {
-        match (palette >> (color * 2)) & 0b11 {
-            0b00 => COLOR_WHITE,
-            0b01 => COLOR_LIGHT_GRAY,
-            0b10 => COLOR_DARK_GRAY,
-            0b11 => COLOR_BLACK,
-            _ => unreachable!(),
+        self.cycles += 1;
+        if self.cycles == SCANLINE_DOTS {
+            self.cycles = 0;
+            self.ly += 1;
+            if self.ly == 144 {
+                self.set_mode(MODE_VBLANK);
+                self.publish_frame();
+                self.skip_frame = false;
+                needs_interrupt |= 1; // VBLANK interrupt
+            } else if self.ly < 144 || self.ly == 154 {
+                if self.ly == 154 {
+                    self.ly = 0;
+                    self.window_line = 0;
+                    self.window_y_triggered = false;
+                    self.window_next_line = false;
+                }
+                if self.ly == self.window_y {
+                    self.window_y_triggered = true;
+                }
+                self.set_mode(MODE_OAM);
+            }
+            self.compare_ly_lyc();
         }
-    }
 
-    fn compare_ly_lyc(&mut self) -> u8 {
-        let result = self.ly == self.ly_compare;
-        self.lcd_status.toggle_bit(2, result); // set ly=lyc flag
+        needs_interrupt | self.update_stat_line()
+    }
 
-        // if ly=lyc interrupt is enabled
-        if result && self.lcd_status.test_bit(6) {
+    // returns the STAT interrupt on a rising edge of the combined interrupt line
+    fn update_stat_line(&mut self) -> u8 {
+        let mode = self.mode();
+        let line = self.lcd_enabled()
+            && ((self.lcd_status.test_bit(3) && mode == MODE_HBLANK)
+                || (self.lcd_status.test_bit(4) && mode == MODE_VBLANK)
+                || (self.lcd_status.test_bit(5) && mode == MODE_OAM)
+                || (self.lcd_status.test_bit(6) && self.lcd_status.test_bit(2)));
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        if rising {
             2 // LCD STAT interrupt
         } else {
             0
         }
     }
 
+    fn publish_frame(&mut self) {
+        self.frame_buffer = self.video_buffer;
+        self.frame_shades = self.video_shades;
+        self.frame_ready = true;
+        self.frame_count += 1;
+    }
+
+    fn disable_lcd(&mut self) {
+        self.ly = 0;
+        self.lx = 0;
+        self.cycles = 0;
+        self.set_mode(MODE_HBLANK);
+        self.stat_line = false;
+        self.off_cycles = 0;
+        self.video_buffer.fill(self.colors.bg[0]);
+        self.video_shades.fill(0);
+    }
+
+    fn enable_lcd(&mut self) {
+        self.ly = 0;
+        self.cycles = 0;
+        self.set_mode(MODE_HBLANK);
+        self.first_line = true;
+        self.skip_frame = true;
+        self.window_line = 0;
+        self.window_y_triggered = self.window_y == 0;
+        self.window_next_line = false;
+        self.compare_ly_lyc();
+        self.pending_interrupt |= self.update_stat_line();
+    }
+
+    pub fn vram_accessible(&self) -> bool {
+        !self.lcd_enabled() || self.mode() != MODE_VRAM
+    }
+
+    pub fn oam_accessible(&self) -> bool {
+        !self.lcd_enabled() || self.mode() == MODE_HBLANK || self.mode() == MODE_VBLANK
+    }
+
+    pub fn get_shade(palette: u8, color: u8) -> u8 {
+        (palette >> (color * 2)) & 0b11
+    }
+
+    fn compare_ly_lyc(&mut self) {
+        let result = self.ly == self.ly_compare;
+        self.lcd_status.toggle_bit(2, result); // set ly=lyc flag
+    }
+
     fn mode(&self) -> u8 {
         self.lcd_status & 0b11
     }
@@ -245,8 +415,8 @@ impl GPU {
     fn update_sprite(&mut self, address: usize) {
         let address = address & 0xFFFC; // round to multiple of 4
         let sprite = address / 4;
-        self.sprites[sprite].y = self.oam[address].wrapping_sub(16);
-        self.sprites[sprite].x = self.oam[address + 1].wrapping_sub(8);
+        self.sprites[sprite].y = self.oam[address];
+        self.sprites[sprite].x = self.oam[address + 1];
         self.sprites[sprite].tile_index = self.oam[address + 2];
         let flags = self.oam[address + 3];
         self.sprites[sprite].bg_priority = flags.test_bit(7);
@@ -255,100 +425,260 @@ impl GPU {
         self.sprites[sprite].palette = flags.test_bit(4);
     }
 
-    fn render_scanline(&mut self) {
-        if self.lcd_control.test_bit(0) {
-            self.render_tiles();
-        }
-        if self.lcd_control.test_bit(1) {
-            self.render_sprites();
+    fn start_pixel_transfer(&mut self) {
+        self.lx = 0;
+        self.discard = self.scroll_x % 8;
+        self.bg_fifo.clear();
+        self.sprite_fifo.clear();
+        self.fetcher = Fetcher::new(false);
+        self.sprite_fetch = None;
+        self.window_rendered = false;
+        if self.window_next_line && self.lcd_control.test_bit(5) {
+            self.fetcher = Fetcher::new(true);
+            self.window_rendered = true;
+            self.discard = 0;
         }
+        self.window_next_line = false;
+        self.scan_oam();
     }
 
-    fn render_tiles(&mut self) {
-        let using_window = self.lcd_control.test_bit(5) && self.window_y <= self.ly;
-        let unsigned = self.lcd_control.test_bit(4);
+    fn end_pixel_transfer(&mut self) {
+        if self.window_rendered {
+            self.window_line += 1;
+        }
+        self.window_next_line =
+            self.window_x == 166 && self.window_y_triggered && self.lcd_control.test_bit(5);
+    }
 
-        let background_mem = if using_window {
-            if self.lcd_control.test_bit(6) {
-                0x9C00
-            } else {
-                0x9800
-            }
-        } else {
-            if self.lcd_control.test_bit(3) {
-                0x9C00
-            } else {
-                0x9800
+    // selects the first ten sprites in OAM order that overlap the current line
+    fn scan_oam(&mut self) {
+        let line = self.ly as u16 + 16;
+        let size_y = self.sprite_height() as u16;
+        self.line_sprites.clear();
+        for (index, sprite) in self.sprites.iter().enumerate() {
+            if line >= sprite.y as u16 && line < sprite.y as u16 + size_y {
+                self.line_sprites.push(index);
+                if self.line_sprites.len() == SPRITES_PER_LINE {
+                    break;
+                }
             }
-        };
+        }
+    }
 
-        let y = if using_window {
-            self.ly.wrapping_sub(self.window_y)
+    fn sprite_height(&self) -> u8 {
+        if self.lcd_control.test_bit(2) {
+            16
         } else {
-            self.ly.wrapping_add(self.scroll_y)
-        };
+            8
+        }
+    }
 
-        let tile_row = (y / 8) as u16 * 32;
+    fn step_pixel_transfer(&mut self) {
+        if !self.fetcher.window && self.window_triggered() {
+            self.fetcher = Fetcher::new(true);
+            self.bg_fifo.clear();
+            self.window_rendered = true;
+            // with WX below 7 the leftmost window pixels are off screen
+            self.discard += 7_u8.saturating_sub(self.window_x);
+        }
 
-        let corrected_window_x = self.window_x.wrapping_sub(7);
+        if self.discard == 0 && self.sprite_fetch.is_none() && self.lcd_control.test_bit(1) {
+            self.sprite_fetch = self.next_sprite().map(|index| (index, 0));
+        }
 
-        for pixel in 0_u8..160_u8 {
-            let x = if using_window && pixel >= corrected_window_x {
-                pixel.wrapping_sub(corrected_window_x)
-            } else {
-                pixel.wrapping_add(self.scroll_x)
-            };
-            let tile_col = (x / 8) as u16;
-            let tile_address = background_mem + tile_row + tile_col;
-            let tile_offset = self.read(tile_address as usize);
-            let tile_index = if unsigned {
-                tile_offset as usize
+        if let Some((index, dots)) = self.sprite_fetch {
+            // the background fetch in progress has to finish before the sprite is fetched
+            if self.bg_fifo.is_empty() {
+                self.step_fetcher();
+            } else if dots + 1 < SPRITE_FETCH_DOTS {
+                self.sprite_fetch = Some((index, dots + 1));
             } else {
-                tile_offset.wrapping_add(128) as usize + 128
-            };
-
-            let tile_color_index = self.tiles[tile_index][y as usize % 8][x as usize % 8];
-            let color = GPU::get_color(self.bg_palette, tile_color_index);
+                self.load_sprite(index);
+                self.sprite_fetch = None;
+            }
+            return;
+        }
 
-            self.video_buffer[self.ly as usize * 160 + pixel as usize] = color;
+        self.step_fetcher();
+        if !self.bg_fifo.is_empty() {
+            self.shift_pixel();
         }
     }
 
-    fn render_sprites(&mut self) {
-        let using_8x16 = self.lcd_control.test_bit(2);
+    // WX=0 triggers while the SCX fine scroll is still being discarded, which makes the
+    // window stutter with SCX like on hardware
+    fn window_triggered(&self) -> bool {
+        self.lcd_control.test_bit(5)
+            && self.window_y_triggered
+            && self.window_x < 166
+            && (self.discard == 0 || self.window_x == 0)
+            && self.lx as u16 + 7 >= self.window_x as u16
+    }
+
+    // on DMG the sprite with the smaller X wins, ties are broken by OAM order
+    fn next_sprite(&mut self) -> Option<usize> {
+        let position = (0..self.line_sprites.len())
+            .filter(|position| {
+                self.sprites[self.line_sprites[*position]].x as u16 <= self.lx as u16 + 8
+            })
+            .min_by_key(|position| {
+                let index = self.line_sprites[*position];
+                (self.sprites[index].x, index)
+            })?;
+        Some(self.line_sprites.remove(position))
+    }
 
-        for sprite in &self.sprites {
-            let size_y = if using_8x16 { 16 } else { 8 };
-            if self.ly < sprite.y || self.ly >= sprite.y + size_y {
-                continue;
+    fn step_fetcher(&mut self) {
+        self.fetcher.dots += 1;
+        match self.fetcher.step {
+            FetchStep::Tile if self.fetcher.dots == 2 => {
+                let (map, x, y) = if self.fetcher.window {
+                    let map = if self.lcd_control.test_bit(6) {
+                        0x1C00
+                    } else {
+                        0x1800
+                    };
+                    (map, self.fetcher.tile_x, self.window_line)
+                } else {
+                    let map = if self.lcd_control.test_bit(3) {
+                        0x1C00
+                    } else {
+                        0x1800
+                    };
+                    let x = (self.scroll_x / 8).wrapping_add(self.fetcher.tile_x) & 0x1F;
+                    (map, x, self.ly.wrapping_add(self.scroll_y))
+                };
+                self.fetcher.tile = self.vram[map + (y as usize / 8) * 32 + x as usize];
+                self.fetcher.row = y % 8;
+                self.fetcher.step = FetchStep::DataLo;
+                self.fetcher.dots = 0;
             }
-            let mut y = (self.ly - sprite.y) % 8; // modulo 8 because of 8x16 sprites
-            if sprite.y_flip {
-                y = 7 - y;
+            FetchStep::DataLo if self.fetcher.dots == 2 => {
+                self.fetcher.data_lo = self.vram[self.bg_tile_address()];
+                self.fetcher.step = FetchStep::DataHi;
+                self.fetcher.dots = 0;
             }
+            FetchStep::DataHi if self.fetcher.dots == 2 => {
+                self.fetcher.data_hi = self.vram[self.bg_tile_address() + 1];
+                self.fetcher.step = FetchStep::Push;
+                self.fetcher.dots = 0;
+            }
+            FetchStep::Push if self.bg_fifo.is_empty() => {
+                // the very first fetch of a line is thrown away
+                if self.fetcher.first_fetch {
+                    self.fetcher.first_fetch = false;
+                } else {
+                    for x in 0..8 {
+                        let bit_lo = (self.fetcher.data_lo >> (7 - x)) & 0b1;
+                        let bit_hi = (self.fetcher.data_hi >> (7 - x)) & 0b1;
+                        self.bg_fifo.push_back((bit_hi << 1) | bit_lo);
+                    }
+                    self.fetcher.tile_x = self.fetcher.tile_x.wrapping_add(1);
+                }
+                self.fetcher.step = FetchStep::Tile;
+                self.fetcher.dots = 0;
+            }
+            _ => (),
+        }
+    }
+
+    fn bg_tile_address(&self) -> usize {
+        let tile = if self.lcd_control.test_bit(4) {
+            self.fetcher.tile as usize * 16
+        } else {
+            (0x1000 + self.fetcher.tile as i8 as isize * 16) as usize
+        };
+        tile + self.fetcher.row as usize * 2
+    }
 
-            let tile = self.tiles[sprite.tile_index as usize];
+    fn load_sprite(&mut self, index: usize) {
+        let sprite = self.sprites[index];
+        let size_y = self.sprite_height();
+        // OAM can change after the scan, e.g. by DMA, a sprite no longer on this line is skipped
+        let row = (self.ly + 16).wrapping_sub(sprite.y);
+        if row >= size_y {
+            return;
+        }
+        let mut y = row;
+        if sprite.y_flip {
+            y = size_y - 1 - y;
+        }
 
-            for pixel in 0..8 {
-                let mut x = pixel;
-                if sprite.x_flip {
-                    x = 7 - x;
-                }
-                let color_index = tile[y as usize][x as usize];
-                if color_index == 0 {
-                    continue;
-                }
-                let palette = if sprite.palette {
-                    self.obj_palette_1
+        // in 8x16 mode the upper tile is always even and the lower tile odd
+        let tile = if size_y == 16 {
+            (sprite.tile_index & 0xFE) + y / 8
+        } else {
+            sprite.tile_index
+        };
+        let address = tile as usize * 16 + (y % 8) as usize * 2;
+        let data_lo = self.vram[address];
+        let data_hi = self.vram[address + 1];
+
+        // pixels left of the screen edge were already passed
+        let skip = (self.lx + 8).saturating_sub(sprite.x);
+
+        for pixel in skip..8 {
+            let x = if sprite.x_flip { 7 - pixel } else { pixel };
+            let bit_lo = (data_lo >> (7 - x)) & 0b1;
+            let bit_hi = (data_hi >> (7 - x)) & 0b1;
+            let sprite_pixel = SpritePixel {
+                color: (bit_hi << 1) | bit_lo,
+                palette: sprite.palette,
+                bg_priority: sprite.bg_priority,
+            };
+            // pixels of sprites fetched earlier keep priority unless they are transparent
+            let slot = (pixel - skip) as usize;
+            if slot >= self.sprite_fifo.len() {
+                self.sprite_fifo.push_back(sprite_pixel);
+            } else if self.sprite_fifo[slot].color == 0 {
+                self.sprite_fifo[slot] = sprite_pixel;
+            }
+        }
+    }
+
+    fn shift_pixel(&mut self) {
+        let bg_color = self.bg_fifo.pop_front().unwrap();
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
+        }
+        let sprite = self.sprite_fifo.pop_front();
+
+        let bg_color = if self.lcd_control.test_bit(0) {
+            bg_color
+        } else {
+            0
+        };
+        let mut shade = GPU::get_shade(self.bg_palette, bg_color);
+        let mut color = self.colors.bg[shade as usize];
+
+        // BG-over-OBJ only hides sprites behind background color index 1-3
+        if let Some(sprite) = sprite {
+            if self.lcd_control.test_bit(1)
+                && sprite.color != 0
+                && (!sprite.bg_priority || bg_color == 0)
+            {
+                if sprite.palette {
+                    shade = GPU::get_shade(self.obj_palette_1, sprite.color);
+                    color = self.colors.obj1[shade as usize];
                 } else {
-                    self.obj_palette_0
-                };
-                let color = GPU::get_color(palette, color_index);
-                let index = self.ly as usize * 160 + sprite.x.wrapping_add(pixel) as usize;
-                if !sprite.bg_priority || self.video_buffer[index] == COLOR_WHITE {
-                    self.video_buffer[index] = color;
+                    shade = GPU::get_shade(self.obj_palette_0, sprite.color);
+                    color = self.colors.obj0[shade as usize];
                 }
             }
         }
+
+        if !self.skip_frame {
+            let index = self.ly as usize * SCREEN_WIDTH + self.lx as usize;
+            self.video_buffer[index] = color;
+            self.video_shades[index] = shade;
+        }
+        self.lx += 1;
+    }
+}
+
+impl Default for GPU {
+    fn default() -> Self {
+        Self::new()
     }
 }
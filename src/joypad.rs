@@ -1,3 +1,4 @@
+use crate::sgb::SGB;
 use crate::traits::*;
 
 pub const KEY_UP: u8 = 2;
@@ -12,6 +13,7 @@ pub const KEY_SELECT: u8 = 6;
 pub struct JoyPad {
     pub joypad_state: u8,
     pub input: u8,
+    pub sgb: Option<SGB>,
 }
 
 impl Memory for JoyPad {
@@ -24,7 +26,12 @@ impl Memory for JoyPad {
 
     fn write(&mut self, address: usize, data: u8) {
         match address {
-            0xFF00 => self.input = data,
+            0xFF00 => {
+                self.input = data;
+                if let Some(sgb) = self.sgb.as_mut() {
+                    sgb.write_joypad(data);
+                }
+            }
             _ => panic!("Invalid JoyPad address"),
         }
     }
@@ -35,6 +42,7 @@ impl JoyPad {
         JoyPad {
             joypad_state: 0xCF,
             input: 0x00,
+            sgb: None,
         }
     }
 
@@ -58,12 +66,32 @@ impl JoyPad {
 
     pub fn get_joypad_state(&self) -> u8 {
         let res = self.input ^ 0xFF; // TODO: move to constant
-        if !res.test_bit(4) {
-            res & ((self.joypad_state >> 4) | 0xF0)
+
+        // with no keys selected, the SGB returns the current controller
+        let sgb_id = self
+            .sgb
+            .as_ref()
+            .and_then(|sgb| sgb.joypad_id())
+            .filter(|_| self.input & 0x30 == 0x30);
+        // only the first SGB controller is connected to the keyboard
+        let joypad_state = match &self.sgb {
+            Some(sgb) if sgb.player != 0 => 0xFF,
+            _ => self.joypad_state,
+        };
+        if let Some(id) = sgb_id {
+            0xF0 | id
+        } else if !res.test_bit(4) {
+            res & ((joypad_state >> 4) | 0xF0)
         } else if !res.test_bit(5) {
-            res & ((self.joypad_state & 0xF) | 0xF0)
+            res & ((joypad_state & 0xF) | 0xF0)
         } else {
             res
         }
     }
 }
+
+impl Default for JoyPad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
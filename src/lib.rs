@@ -0,0 +1,30 @@
+pub mod callstack;
+pub mod cartridge;
+pub mod cheats;
+pub mod cli;
+pub mod colorization;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod emulator;
+pub mod filter;
+pub mod gdb;
+pub mod gpu;
+pub mod joypad;
+pub mod memview;
+pub mod mmu;
+pub mod palette;
+pub mod patch;
+pub mod png;
+pub mod printer;
+pub mod profiler;
+pub mod recorder;
+pub mod rtc;
+pub mod search;
+pub mod serial;
+pub mod sgb;
+pub mod symbols;
+pub mod trace;
+pub mod traits;
+pub mod viewer;
+pub mod watchpoint;
@@ -1,14 +1,85 @@
-mod cartridge;
-mod cpu;
-mod emulator;
-mod gpu;
-mod joypad;
-mod mmu;
-mod rtc;
-mod traits;
-
-use emulator::Emulator;
+use gb_emu::cartridge::read_rom;
+use gb_emu::cli::{parse_args, Command, Options, USAGE};
+use gb_emu::disasm::disassemble_rom;
+use gb_emu::emulator::Emulator;
+use gb_emu::symbols::Symbols;
+use gb_emu::trace::Tracer;
+use std::io::Write;
+
 fn main() {
-    let mut emulator = Emulator::new("./roms/pikachu.gb", "./saves/pikachu.sav");
-    emulator.run();
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if options.command == Command::Disassemble {
+        disassemble(&options).unwrap_or_else(|e| println!("Failed to disassemble: {}", e));
+        return;
+    }
+
+    let mut emulator = Emulator::new(&options.rom_path, &options.save_path);
+    emulator.set_patch_path(options.patch_path.as_deref());
+    if let Some(path) = &options.symbol_path {
+        emulator
+            .load_symbols(path)
+            .unwrap_or_else(|e| println!("Failed to load symbols: {}", e));
+    }
+    if let Some(printer_dir) = &options.printer_dir {
+        emulator.attach_printer(printer_dir);
+    }
+    emulator.set_sgb_mode(options.sgb);
+    emulator.set_colorization(options.colorize);
+    if let Some(path) = &options.record_path {
+        emulator.set_record_path(path);
+    }
+
+    if let Some(path) = &options.trace_path {
+        match Tracer::to_file(path, options.trace_format) {
+            Ok(mut tracer) => {
+                tracer.pc_range = options.trace_pc.clone();
+                tracer.bank = options.trace_bank;
+                emulator.set_tracer(Some(tracer));
+            }
+            Err(e) => println!("Failed to open trace file: {}", e),
+        }
+    }
+
+    emulator.set_dump_vram(options.dump_vram);
+    emulator
+        .load_cheats(&options.cheat_path)
+        .unwrap_or_else(|e| println!("Failed to load cheats: {}", e));
+    if options.profile {
+        emulator.start_profiling();
+    }
+    if options.debug {
+        emulator.pause();
+    }
+    if let Some(port) = options.gdb_port {
+        emulator.set_gdb_port(port);
+    }
+
+    if options.headless {
+        emulator.run_headless(options.frames.unwrap_or(u64::MAX));
+    } else {
+        emulator.run();
+    }
+}
+
+fn disassemble(options: &Options) -> std::io::Result<()> {
+    let rom = read_rom(&options.rom_path, options.patch_path.as_deref())?;
+    let symbols = match &options.symbol_path {
+        Some(path) => Symbols::load(path)?,
+        None => Symbols::new(),
+    };
+    let disassembly = disassemble_rom(&rom, options.bank, &symbols);
+    match &options.output_path {
+        Some(path) => std::fs::write(path, disassembly),
+        None => std::io::stdout().write_all(disassembly.as_bytes()),
+    }
 }
@@ -0,0 +1,298 @@
+use crate::mmu::{IO_REGISTERS, MMU};
+use crate::traits::TestBit;
+use std::io::{Error, ErrorKind, Result};
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+const BYTES_PER_LINE: u16 = 16;
+
+// changed bytes are shown inverted
+const HIGHLIGHT: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+
+/// Shows the 64 KB address space as the cpu sees it, or with other rom and ram banks mapped.
+pub struct MemoryViewer {
+    pub rom_bank: Option<usize>, // the mapped bank when not set
+    pub ram_bank: Option<usize>,
+    pub highlight: bool,
+    snapshot: Vec<u8>, // memory when execution last resumed
+    ram_snapshot: Vec<u8>,
+}
+
+impl MemoryViewer {
+    pub fn new() -> MemoryViewer {
+        MemoryViewer {
+            rom_bank: None,
+            ram_bank: None,
+            highlight: true,
+            snapshot: Vec::new(),
+            ram_snapshot: Vec::new(),
+        }
+    }
+
+    pub fn read(&self, mmu: &MMU, address: u16) -> u8 {
+        match self.banked_offset(mmu, address) {
+            Some(Banked::Rom(offset)) => mmu.cartrige.as_ref().unwrap().rom()[offset],
+            Some(Banked::Ram(offset)) => mmu.cartrige.as_ref().unwrap().ram()[offset],
+            None => mmu.peek(address),
+        }
+    }
+
+    /// Writes a byte, straight into the selected ram bank or through the bus otherwise.
+    /// Rom can't be written, on the bus those writes would switch banks instead.
+    pub fn write(&self, mmu: &mut MMU, address: u16, value: u8) -> Result<()> {
+        if address < 0x8000 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Can't write to rom at {:04X}", address),
+            ));
+        }
+        match self.banked_offset(mmu, address) {
+            Some(Banked::Ram(offset)) => mmu.cartrige.as_mut().unwrap().ram_mut()[offset] = value,
+            _ => mmu.poke(address, value),
+        }
+        Ok(())
+    }
+
+    /// Remembers the memory to highlight what changed since.
+    pub fn snapshot(&mut self, mmu: &MMU) {
+        self.snapshot = (0..=0xFFFF).map(|address| mmu.peek(address)).collect();
+        self.ram_snapshot = mmu
+            .cartrige
+            .as_ref()
+            .map_or(Vec::new(), |cartridge| cartridge.ram().to_vec());
+    }
+
+    pub fn changed(&self, mmu: &MMU, address: u16) -> bool {
+        let previous = match self.banked_offset(mmu, address) {
+            Some(Banked::Rom(_)) => return false,
+            Some(Banked::Ram(offset)) => self.ram_snapshot.get(offset),
+            None => self.snapshot.get(address as usize),
+        };
+        previous.is_some_and(|previous| *previous != self.read(mmu, address))
+    }
+
+    /// Lines of 16 bytes in hex and ascii from an address.
+    pub fn dump(&self, mmu: &MMU, address: u16, length: u16) -> Vec<String> {
+        let mut lines = Vec::new();
+        for offset in (0..length).step_by(BYTES_PER_LINE as usize) {
+            let start = address.wrapping_add(offset);
+            let addresses = (0..BYTES_PER_LINE.min(length - offset)).map(|i| start.wrapping_add(i));
+            let mut hex = Vec::new();
+            let mut ascii = String::new();
+            for address in addresses {
+                let value = self.read(mmu, address);
+                let byte = format!("{:02X}", value);
+                if self.highlight && self.changed(mmu, address) {
+                    hex.push(format!("{}{}{}", HIGHLIGHT, byte, RESET));
+                } else {
+                    hex.push(byte);
+                }
+                ascii.push(match value {
+                    0x20..=0x7E => value as char,
+                    _ => '.',
+                });
+            }
+            // the escape codes don't take up space, so short lines are padded by hand
+            let padding = " ".repeat((BYTES_PER_LINE as usize - ascii.len()) * 3);
+            lines.push(format!(
+                "{}:{:04X}  {}{}  {}",
+                self.bank_name(mmu, start),
+                start,
+                hex.join(" "),
+                padding,
+                ascii
+            ));
+        }
+        lines
+    }
+
+    /// The named io registers with their bits decoded, optionally only the one called `name`.
+    pub fn io_panel(&self, mmu: &MMU, name: Option<&str>) -> Vec<String> {
+        IO_REGISTERS
+            .iter()
+            .filter(|(_, register)| name.is_none_or(|name| register.eq_ignore_ascii_case(name)))
+            .map(|(address, register)| {
+                let value = mmu.peek(*address);
+                let marker = if self.highlight && self.changed(mmu, *address) {
+                    '*'
+                } else {
+                    ' '
+                };
+                format!(
+                    "{:04X} {:<4} {}{:02X} {:08b}  {}",
+                    address,
+                    register,
+                    marker,
+                    value,
+                    value,
+                    describe_register(*address, value)
+                )
+            })
+            .collect()
+    }
+
+    // the bank shown for an address, `--` outside of rom and cartridge ram
+    fn bank_name(&self, mmu: &MMU, address: u16) -> String {
+        let bank = match address {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF => self.rom_bank.or(mmu.rom_bank(address)),
+            0xA000..=0xBFFF => self.ram_bank,
+            _ => None,
+        };
+        bank.map_or("--".to_string(), |bank| format!("{:02X}", bank))
+    }
+
+    fn banked_offset(&self, mmu: &MMU, address: u16) -> Option<Banked> {
+        let cartridge = mmu.cartrige.as_ref()?;
+        let offset = match (address, self.rom_bank, self.ram_bank) {
+            (0x4000..=0x7FFF, Some(bank), _) => {
+                Banked::Rom(bank * ROM_BANK_SIZE + address as usize - 0x4000)
+            }
+            (0xA000..=0xBFFF, _, Some(bank)) => {
+                Banked::Ram(bank * RAM_BANK_SIZE + address as usize - 0xA000)
+            }
+            _ => return None,
+        };
+        // banks past the end of the cartridge read through the bus
+        let size = match offset {
+            Banked::Rom(offset) => (offset, cartridge.rom().len()),
+            Banked::Ram(offset) => (offset, cartridge.ram().len()),
+        };
+        (size.0 < size.1).then_some(offset)
+    }
+}
+
+impl Default for MemoryViewer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Banked {
+    Rom(usize),
+    Ram(usize),
+}
+
+// https://gbdev.io/pandocs/Hardware_Reg_List.html
+pub fn describe_register(address: u16, value: u8) -> String {
+    let bit = |bit: u8| value.test_bit(bit) as u8;
+    let flags = |names: &[&str]| {
+        let set = names
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| value.test_bit(*index as u8))
+            .map(|(_, name)| *name)
+            .collect::<Vec<&str>>();
+        if set.is_empty() {
+            "-".to_string()
+        } else {
+            set.join(" ")
+        }
+    };
+    let length = value & 0x3F;
+    let duty = value >> 6;
+    let envelope = || {
+        format!(
+            "volume={} {} pace={}",
+            value >> 4,
+            if value.test_bit(3) { "up" } else { "down" },
+            value & 0x07
+        )
+    };
+    let control = || {
+        format!(
+            "trigger={} length_enable={} period_hi={}",
+            bit(7),
+            bit(6),
+            value & 0x07
+        )
+    };
+
+    match address {
+        0xFF00 => format!(
+            "select_buttons={} select_dpad={} inputs={:04b}",
+            1 - bit(5),
+            1 - bit(4),
+            value & 0x0F
+        ),
+        0xFF02 => format!("transfer={} internal_clock={}", bit(7), bit(0)),
+        0xFF07 => {
+            let clock = [4096, 262144, 65536, 16384][value as usize & 0x03];
+            format!("enable={} clock={}Hz", bit(2), clock)
+        }
+        0xFF0F | 0xFFFF => flags(&["VBLANK", "STAT", "TIMER", "SERIAL", "JOYPAD"]),
+        0xFF10 => format!(
+            "pace={} {} step={}",
+            (value >> 4) & 0x07,
+            if value.test_bit(3) { "down" } else { "up" },
+            value & 0x07
+        ),
+        0xFF11 | 0xFF16 => format!("duty={} length={}", duty, length),
+        0xFF12 | 0xFF17 | 0xFF21 => envelope(),
+        0xFF13 | 0xFF18 | 0xFF1D => format!("period_lo={}", value),
+        0xFF14 | 0xFF19 | 0xFF1E => control(),
+        0xFF23 => format!("trigger={} length_enable={}", bit(7), bit(6)),
+        0xFF1A => format!("dac={}", bit(7)),
+        0xFF1B => format!("length={}", value),
+        0xFF1C => {
+            let level = ["mute", "100%", "50%", "25%"][(value as usize >> 5) & 0x03];
+            format!("level={}", level)
+        }
+        0xFF20 => format!("length={}", length),
+        0xFF22 => format!(
+            "shift={} width={} divider={}",
+            value >> 4,
+            if value.test_bit(3) { 7 } else { 15 },
+            value & 0x07
+        ),
+        0xFF24 => format!(
+            "vin_left={} left={} vin_right={} right={}",
+            bit(7),
+            (value >> 4) & 0x07,
+            bit(3),
+            value & 0x07
+        ),
+        0xFF25 => format!("left={:04b} right={:04b}", value >> 4, value & 0x0F),
+        0xFF26 => format!(
+            "on={} ch4={} ch3={} ch2={} ch1={}",
+            bit(7),
+            bit(3),
+            bit(2),
+            bit(1),
+            bit(0)
+        ),
+        0xFF40 => format!(
+            "lcd={} window_map={} window={} tiles={} bg_map={} obj_size={} obj={} bg={}",
+            bit(7),
+            if value.test_bit(6) { "9C00" } else { "9800" },
+            bit(5),
+            if value.test_bit(4) { "8000" } else { "8800" },
+            if value.test_bit(3) { "9C00" } else { "9800" },
+            if value.test_bit(2) { "8x16" } else { "8x8" },
+            bit(1),
+            bit(0)
+        ),
+        0xFF41 => {
+            let mode = ["HBLANK", "VBLANK", "OAM", "DRAW"][value as usize & 0x03];
+            format!(
+                "int_lyc={} int_oam={} int_vblank={} int_hblank={} lyc={} mode={}",
+                bit(6),
+                bit(5),
+                bit(4),
+                bit(3),
+                bit(2),
+                mode
+            )
+        }
+        0xFF46 => format!("source={:02X}00", value),
+        0xFF47..=0xFF49 => format!(
+            "shades={} {} {} {}",
+            value & 0x03,
+            (value >> 2) & 0x03,
+            (value >> 4) & 0x03,
+            value >> 6
+        ),
+        _ => format!("{}", value),
+    }
+}
@@ -1,10 +1,20 @@
-use crate::{cartridge::Cartridge, gpu::GPU, joypad::JoyPad, rtc::RTC, traits::Memory};
+use crate::{
+    cartridge::Cartridge,
+    cheats::Cheats,
+    gpu::GPU,
+    joypad::JoyPad,
+    rtc::RTC,
+    serial::Serial,
+    traits::Memory,
+    watchpoint::{Access, Watchpoints},
+};
 
 pub struct MMU {
     pub cartrige: Option<Box<dyn Cartridge>>,
     pub gpu: GPU,
     pub rtc: RTC,
     pub joypad: JoyPad,
+    pub serial: Serial,
 
     pub wram: [u8; 0x2000], // work ram
     pub hram: [u8; 0x7F],   // high ram
@@ -12,8 +22,64 @@ pub struct MMU {
     pub interrupt_flag: u8,
     pub io_backup: [u8; 0x80],
     pub dma: u8,
+    pub dma_active: bool,
+    pub dma_source: u16,
+    pub dma_index: u16,
+    pub dma_pending: Option<(u16, u8)>, // source and remaining startup M-cycles
+    pub dma_cycles: u16,
+    pub watchpoints: Watchpoints,
+    pub cheats: Cheats,
 }
 
+// https://gbdev.io/pandocs/Hardware_Reg_List.html
+pub const IO_REGISTERS: &[(u16, &str)] = &[
+    (0xFF00, "P1"),
+    (0xFF01, "SB"),
+    (0xFF02, "SC"),
+    (0xFF04, "DIV"),
+    (0xFF05, "TIMA"),
+    (0xFF06, "TMA"),
+    (0xFF07, "TAC"),
+    (0xFF0F, "IF"),
+    (0xFF10, "NR10"),
+    (0xFF11, "NR11"),
+    (0xFF12, "NR12"),
+    (0xFF13, "NR13"),
+    (0xFF14, "NR14"),
+    (0xFF16, "NR21"),
+    (0xFF17, "NR22"),
+    (0xFF18, "NR23"),
+    (0xFF19, "NR24"),
+    (0xFF1A, "NR30"),
+    (0xFF1B, "NR31"),
+    (0xFF1C, "NR32"),
+    (0xFF1D, "NR33"),
+    (0xFF1E, "NR34"),
+    (0xFF20, "NR41"),
+    (0xFF21, "NR42"),
+    (0xFF22, "NR43"),
+    (0xFF23, "NR44"),
+    (0xFF24, "NR50"),
+    (0xFF25, "NR51"),
+    (0xFF26, "NR52"),
+    (0xFF40, "LCDC"),
+    (0xFF41, "STAT"),
+    (0xFF42, "SCY"),
+    (0xFF43, "SCX"),
+    (0xFF44, "LY"),
+    (0xFF45, "LYC"),
+    (0xFF46, "DMA"),
+    (0xFF47, "BGP"),
+    (0xFF48, "OBP0"),
+    (0xFF49, "OBP1"),
+    (0xFF4A, "WY"),
+    (0xFF4B, "WX"),
+    (0xFFFF, "IE"),
+];
+
+const DMA_LENGTH: u16 = 0xA0;
+const DMA_STARTUP_DELAY: u8 = 1; // M-cycles between the write to 0xFF46 and the first byte
+
 impl MMU {
     pub fn new() -> MMU {
         let mut io_backup = [0; 0x80];
@@ -54,33 +120,137 @@ impl MMU {
             gpu: GPU::new(),
             rtc: RTC::new(),
             joypad: JoyPad::new(),
+            serial: Serial::new(),
             wram: [0; 0x2000],
             hram: [0; 0x7F],
             interrupt_enable: 0x00,
             interrupt_flag: 0xE1,
             io_backup,
             dma: 0xFF,
+            dma_active: false,
+            dma_source: 0x0000,
+            dma_index: 0,
+            dma_pending: None,
+            dma_cycles: 0,
+            watchpoints: Watchpoints::new(),
+            cheats: Cheats::new(),
+        }
+    }
+
+    /// Writes the game shark codes once per frame.
+    pub fn apply_cheats(&mut self) {
+        if self.cheats.frame_count == self.gpu.frame_count {
+            return;
+        }
+        self.cheats.frame_count = self.gpu.frame_count;
+        for (bank, address, value) in self.cheats.writes() {
+            match (bank, address, self.cartrige.as_mut()) {
+                (Some(bank), 0xA000..=0xBFFF, Some(cartridge)) => {
+                    let offset = bank * 0x2000 + address as usize - 0xA000;
+                    if let Some(byte) = cartridge.ram_mut().get_mut(offset) {
+                        *byte = value;
+                    }
+                }
+                _ => self.poke(address, value),
+            }
         }
     }
 
     pub fn dma_transfer(&mut self, data: u8) {
         self.dma = data;
-        let address = (data as u16) << 8;
-        for i in 0..0xA0 {
-            let value = self.read(address + i);
-            self.write(0xFE00 + i, value);
+        // sources above 0xDFFF are mirrored to work ram, as on the echo ram
+        let source = if data >= 0xE0 { data - 0x20 } else { data };
+        // a running transfer keeps going until the restarted one takes over
+        self.dma_pending = Some(((source as u16) << 8, DMA_STARTUP_DELAY));
+    }
+
+    pub fn update_dma(&mut self, cycles: u16) {
+        if !self.dma_active && self.dma_pending.is_none() {
+            return;
+        }
+
+        self.dma_cycles += cycles;
+        while self.dma_cycles >= 4 {
+            self.dma_cycles -= 4;
+
+            if self.dma_active {
+                let value = self.read_memory(self.dma_source + self.dma_index);
+                self.gpu.write(0xFE00 + self.dma_index as usize, value);
+                self.dma_index += 1;
+                if self.dma_index == DMA_LENGTH {
+                    self.dma_active = false;
+                }
+            }
+
+            if let Some((source, delay)) = self.dma_pending {
+                if delay <= 1 {
+                    self.dma_pending = None;
+                    self.dma_active = true;
+                    self.dma_source = source;
+                    self.dma_index = 0;
+                } else {
+                    self.dma_pending = Some((source, delay - 1));
+                }
+            }
+        }
+
+        if !self.dma_active && self.dma_pending.is_none() {
+            self.dma_cycles = 0;
         }
     }
 
     // TODO: replace u16 with usize
     pub fn read(&self, address: u16) -> u8 {
+        let value = self.fetch(address);
+        if self.watchpoints.active {
+            self.watchpoints.check(Access::Read, address, value, value);
+        }
+        value
+    }
+
+    /// Reads an opcode or operand for the cpu, instruction fetches don't trigger read watchpoints.
+    pub fn fetch(&self, address: u16) -> u8 {
+        if self.blocked_by_dma(address) {
+            return 0xFF;
+        }
+        self.read_memory(address)
+    }
+
+    /// Reads a byte for the debugger and cheats, without the limits of the cpu during DMA
+    /// and without triggering watchpoints.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.read_memory(address)
+    }
+
+    // while DMA is running the cpu can only reach high ram and io registers
+    fn blocked_by_dma(&self, address: u16) -> bool {
+        self.dma_active && address < 0xFF00
+    }
+
+    /// The rom bank mapped at an address, `None` outside of the rom.
+    pub fn rom_bank(&self, address: u16) -> Option<usize> {
+        match address {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF => self.cartrige.as_ref().map(|c| c.rom_bank()),
+            _ => None,
+        }
+    }
+
+    fn read_memory(&self, address: u16) -> u8 {
         match address {
             // rom
+            0x0000..=0x7FFF if self.cheats.has_patches() => {
+                let value = self.cartrige.as_ref().unwrap().read(address as usize);
+                self.cheats.patch(address, value)
+            }
             0x0000..=0x7FFF | 0xA000..=0xBFFF => {
                 self.cartrige.as_ref().unwrap().read(address as usize)
             }
             // DMA
             0xFF46 => self.dma,
+            // vram is locked while the ppu draws, OAM during OAM scan and drawing
+            0x8000..=0x9FFF if !self.gpu.vram_accessible() => 0xFF,
+            0xFE00..=0xFE9F if !self.gpu.oam_accessible() => 0xFF,
             // gpu
             0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF4F | 0xFF68..=0xFF6B => {
                 self.gpu.read(address as usize)
@@ -89,6 +259,8 @@ impl MMU {
             0xFF04..=0xFF07 => self.rtc.read(address as usize),
             // IF
             0xFF0F => self.interrupt_flag,
+            // serial
+            0xFF01..=0xFF02 => self.serial.read(address as usize),
             // work ram
             0xC000..=0xDFFF => self.wram[(address - 0xC000) as usize],
             0xE000..=0xFDFF => self.wram[(address - 0xE000) as usize],
@@ -105,7 +277,24 @@ impl MMU {
         }
     }
 
+    /// Writes a byte for the debugger and cheats, without the limits of the cpu during DMA
+    /// and without triggering watchpoints.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.write_memory(address, value);
+    }
+
     pub fn write(&mut self, address: u16, value: u8) {
+        if self.blocked_by_dma(address) {
+            return;
+        }
+        if self.watchpoints.active {
+            let old = self.read_memory(address);
+            self.watchpoints.check(Access::Write, address, old, value);
+        }
+        self.write_memory(address, value);
+    }
+
+    fn write_memory(&mut self, address: u16, value: u8) {
         match address {
             // rom
             0x0000..=0x7FFF | 0xA000..=0xBFFF => self
@@ -115,6 +304,9 @@ impl MMU {
                 .write(address as usize, value),
             // DMA
             0xFF46 => self.dma_transfer(value),
+            // vram is locked while the ppu draws, OAM during OAM scan and drawing
+            0x8000..=0x9FFF if !self.gpu.vram_accessible() => (),
+            0xFE00..=0xFE9F if !self.gpu.oam_accessible() => (),
             // gpu
             0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF4F | 0xFF68..=0xFF6B => {
                 self.gpu.write(address as usize, value)
@@ -133,7 +325,7 @@ impl MMU {
             // IF
             0xFF0F => self.interrupt_flag = value,
             // serial
-            0xFF01 => print!("{}", value as char), // print serial output
+            0xFF01..=0xFF02 => self.serial.write(address as usize, value),
             // IE
             0xFFFF => self.interrupt_enable = value,
             // backup
@@ -141,3 +333,9 @@ impl MMU {
         }
     }
 }
+
+impl Default for MMU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,90 @@
+use crate::gpu::{COLOR_BLACK, COLOR_DARK_GRAY, COLOR_LIGHT_GRAY, COLOR_WHITE};
+use json::JsonValue;
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind, Result};
+
+/// Colors for the four DMG shades, separately for the background and both sprite palettes.
+#[derive(Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub bg: [u32; 4],
+    pub obj0: [u32; 4],
+    pub obj1: [u32; 4],
+}
+
+impl Palette {
+    pub fn new(name: &str, bg: [u32; 4], obj0: [u32; 4], obj1: [u32; 4]) -> Palette {
+        Palette {
+            name: name.to_string(),
+            bg,
+            obj0,
+            obj1,
+        }
+    }
+
+    pub fn uniform(name: &str, colors: [u32; 4]) -> Palette {
+        Palette::new(name, colors, colors, colors)
+    }
+}
+
+pub fn presets() -> Vec<Palette> {
+    vec![
+        Palette::uniform(
+            "Grayscale",
+            [COLOR_WHITE, COLOR_LIGHT_GRAY, COLOR_DARK_GRAY, COLOR_BLACK],
+        ),
+        Palette::uniform("Classic", [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]),
+        Palette::uniform("Pocket", [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]),
+        Palette::uniform("Light", [0x00B581, 0x009A71, 0x00694A, 0x004F3B]),
+        Palette::uniform("High contrast", [0xFFFFFF, 0xB0B0B0, 0x505050, 0x000000]),
+        // Okabe-Ito colors, distinguishable with the common forms of color blindness
+        Palette::new(
+            "Colorblind",
+            [0xFFFFFF, 0xF0E442, 0x0072B2, 0x000000],
+            [0xFFFFFF, 0xE69F00, 0xD55E00, 0x000000],
+            [0xFFFFFF, 0x56B4E9, 0x009E73, 0x000000],
+        ),
+    ]
+}
+
+/// Loads custom palettes from a JSON array like
+/// `[{ "name": "Mine", "bg": ["#E0F8D0", "#88C070", "#346856", "#081820"], "obj0": [...], "obj1": [...] }]`.
+/// `colors` can be given instead of `bg`, `obj0` and `obj1` to use the same colors for all three.
+pub fn load_palettes(path: &str) -> Result<Vec<Palette>> {
+    let data = read_to_string(path)?;
+    let root = json::parse(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    let mut palettes = Vec::new();
+    for entry in root.members() {
+        let name = entry["name"].as_str().unwrap_or("Custom");
+        let palette = if entry.has_key("colors") {
+            Palette::uniform(name, parse_colors(&entry["colors"])?)
+        } else {
+            Palette::new(
+                name,
+                parse_colors(&entry["bg"])?,
+                parse_colors(&entry["obj0"])?,
+                parse_colors(&entry["obj1"])?,
+            )
+        };
+        palettes.push(palette);
+    }
+    Ok(palettes)
+}
+
+fn parse_colors(value: &JsonValue) -> Result<[u32; 4]> {
+    let invalid = || Error::new(ErrorKind::InvalidData, format!("Invalid colors: {}", value));
+    if value.len() != 4 {
+        return Err(invalid());
+    }
+    let mut colors = [0; 4];
+    for (color, entry) in colors.iter_mut().zip(value.members()) {
+        *color = match entry.as_str() {
+            Some(hex) => u32::from_str_radix(hex.trim_start_matches('#'), 16).ok(),
+            None => entry.as_u32(),
+        }
+        .filter(|color| *color <= 0xFFFFFF)
+        .ok_or_else(invalid)?;
+    }
+    Ok(colors)
+}
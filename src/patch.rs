@@ -0,0 +1,404 @@
+use crate::png::crc32;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+// https://zerosoft.zophar.net/ips.php
+// https://www.romhacking.net/documents/392/ (UPS)
+// https://www.romhacking.net/documents/746/ (BPS)
+
+pub const EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+const FOOTER_LENGTH: usize = 12; // source, target and patch crc32
+
+/// A patch with the same name as the rom, e.g. `game.ips` for `game.gb`.
+pub fn find_patch(rom_path: &str) -> Option<String> {
+    EXTENSIONS
+        .iter()
+        .map(|extension| Path::new(rom_path).with_extension(extension))
+        .find(|path| path.exists())
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+/// Applies an IPS, UPS or BPS patch, the format is detected from its header.
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(invalid("Unknown patch format"))
+    }
+}
+
+// records of a 3 byte offset and 2 byte length, a length of 0 repeats one byte
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let mut output = rom.to_vec();
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+    loop {
+        if reader.remaining().starts_with(IPS_EOF) {
+            reader.position += IPS_EOF.len();
+            break;
+        }
+        let offset = reader.read_be(3)?;
+        let (length, data) = match reader.read_be(2)? {
+            0 => {
+                let length = reader.read_be(2)?;
+                (length, vec![reader.read()?; length])
+            }
+            length => (length, reader.read_slice(length)?.to_vec()),
+        };
+        if output.len() < offset + length {
+            output.resize(offset + length, 0);
+        }
+        output[offset..offset + length].copy_from_slice(&data);
+    }
+    // an optional size to truncate the rom to
+    if let Ok(length) = reader.read_be(3) {
+        output.truncate(length);
+    }
+    Ok(output)
+}
+
+// runs of bytes xored with the source, each after a number of unchanged bytes
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let (source_crc, target_crc) = check_footer(patch)?;
+    let mut reader = Reader::new(&patch[..patch.len() - FOOTER_LENGTH], UPS_MAGIC.len());
+    let source_size = reader.read_number()?;
+    let target_size = reader.read_number()?;
+    check_source(rom, source_size, source_crc)?;
+
+    let mut output = rom.to_vec();
+    output.resize(target_size, 0);
+    let mut position = 0;
+    while !reader.remaining().is_empty() {
+        position += reader.read_number()?;
+        loop {
+            let byte = reader.read()?;
+            if byte == 0 {
+                position += 1;
+                break;
+            }
+            if let Some(target) = output.get_mut(position) {
+                *target ^= byte;
+            }
+            position += 1;
+        }
+    }
+    check_target(&output, target_crc)?;
+    Ok(output)
+}
+
+// actions that copy from the source, the patch or the output so far
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let (source_crc, target_crc) = check_footer(patch)?;
+    let mut reader = Reader::new(&patch[..patch.len() - FOOTER_LENGTH], BPS_MAGIC.len());
+    let source_size = reader.read_number()?;
+    let target_size = reader.read_number()?;
+    let metadata_size = reader.read_number()?;
+    reader.read_slice(metadata_size)?;
+    check_source(rom, source_size, source_crc)?;
+
+    let mut output = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0_isize, 0_isize);
+    while !reader.remaining().is_empty() {
+        let data = reader.read_number()?;
+        let length = (data >> 2) + 1;
+        match data & 3 {
+            // source read, from the same position in the source
+            0 => {
+                let start = output.len();
+                let bytes = rom.get(start..start + length).ok_or_else(out_of_bounds)?;
+                output.extend_from_slice(bytes);
+            }
+            // target read, from the patch
+            1 => output.extend_from_slice(reader.read_slice(length)?),
+            // source copy and target copy, from a relative position
+            kind => {
+                let data = reader.read_number()?;
+                let delta = (data >> 1) as isize * if data & 1 == 1 { -1 } else { 1 };
+                let offset = if kind == 2 {
+                    &mut source_offset
+                } else {
+                    &mut target_offset
+                };
+                *offset += delta;
+                for _ in 0..length {
+                    let index = usize::try_from(*offset).map_err(|_| out_of_bounds())?;
+                    // target copies may repeat what they are writing
+                    let byte = if kind == 2 {
+                        rom.get(index)
+                    } else {
+                        output.get(index)
+                    };
+                    output.push(*byte.ok_or_else(out_of_bounds)?);
+                    *offset += 1;
+                }
+            }
+        }
+    }
+    if output.len() != target_size {
+        return Err(invalid("Patched rom has the wrong size"));
+    }
+    check_target(&output, target_crc)?;
+    Ok(output)
+}
+
+// the crc32 of the source and target, after checking the patch itself
+fn check_footer(patch: &[u8]) -> Result<(u32, u32)> {
+    if patch.len() < FOOTER_LENGTH + 4 {
+        return Err(invalid("Patch is too short"));
+    }
+    let footer = &patch[patch.len() - FOOTER_LENGTH..];
+    let crc = |index: usize| u32::from_le_bytes(footer[index..index + 4].try_into().unwrap());
+    if crc32(&patch[..patch.len() - 4]) != crc(8) {
+        return Err(corrupted());
+    }
+    Ok((crc(0), crc(4)))
+}
+
+fn check_source(rom: &[u8], size: usize, crc: u32) -> Result<()> {
+    if rom.len() != size || crc32(rom) != crc {
+        return Err(invalid("Patch is for a different rom"));
+    }
+    Ok(())
+}
+
+fn check_target(rom: &[u8], crc: u32) -> Result<()> {
+    if crc32(rom) != crc {
+        return Err(invalid("Patched rom doesn't match the checksum"));
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Reader<'a> {
+        Reader { data, position }
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position.min(self.data.len())..]
+    }
+
+    fn read(&mut self) -> Result<u8> {
+        Ok(self.read_slice(1)?[0])
+    }
+
+    fn read_slice(&mut self, length: usize) -> Result<&'a [u8]> {
+        let slice = self
+            .remaining()
+            .get(..length)
+            .ok_or_else(|| invalid("Patch ends unexpectedly"))?;
+        self.position += length;
+        Ok(slice)
+    }
+
+    fn read_be(&mut self, length: usize) -> Result<usize> {
+        Ok(self
+            .read_slice(length)?
+            .iter()
+            .fold(0, |value, byte| value << 8 | *byte as usize))
+    }
+
+    // variable length numbers of UPS and BPS, 7 bits per byte until the top bit is set
+    fn read_number(&mut self) -> Result<usize> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.read()?;
+            // numbers too big for an address can only come from a broken patch
+            value = ((byte & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or_else(corrupted)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(corrupted)?;
+            value = value.checked_add(shift).ok_or_else(corrupted)?;
+        }
+    }
+}
+
+fn out_of_bounds() -> Error {
+    invalid("Patch reads past the end of the rom")
+}
+
+fn corrupted() -> Error {
+    invalid("Patch is corrupted")
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the encoders for UPS and BPS numbers and footers
+    fn number(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(0x80 | byte);
+                return bytes;
+            }
+            bytes.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    fn action(kind: usize, length: usize) -> Vec<u8> {
+        number((length - 1) << 2 | kind)
+    }
+
+    fn relative(delta: isize) -> Vec<u8> {
+        number(delta.unsigned_abs() << 1 | (delta < 0) as usize)
+    }
+
+    fn error(result: Result<Vec<u8>>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn ips_records() {
+        let patch = [
+            b"PATCH".as_slice(),
+            &[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB], // 2 bytes at 1
+            &[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x03, 0xCC], // 3 times CC at 4
+            &[0x00, 0x00, 0x08, 0x00, 0x01, 0xDD],       // past the end
+            b"EOF",
+        ]
+        .concat();
+        assert_eq!(
+            apply_patch(&[0; 8], &patch).unwrap(),
+            [0x00, 0xAA, 0xBB, 0x00, 0xCC, 0xCC, 0xCC, 0x00, 0xDD]
+        );
+    }
+
+    #[test]
+    fn ips_truncate() {
+        let patch = [b"PATCH".as_slice(), b"EOF", &[0x00, 0x00, 0x04]].concat();
+        assert_eq!(
+            apply_patch(&[1, 2, 3, 4, 5, 6], &patch).unwrap(),
+            [1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn ips_ends_unexpectedly() {
+        let patch = [b"PATCH".as_slice(), &[0x00, 0x00, 0x01, 0x00, 0x04, 0xAA]].concat();
+        assert_eq!(
+            error(apply_patch(&[0; 8], &patch)),
+            "Patch ends unexpectedly"
+        );
+    }
+
+    #[test]
+    fn ups_xor_runs() {
+        let source = [1, 2, 3, 4, 5, 6];
+        let target = [1, 9, 9, 4, 5, 6, 7];
+        let patch = [
+            b"UPS1".as_slice(),
+            &number(source.len()),
+            &number(target.len()),
+            &number(1),
+            &[2 ^ 9, 3 ^ 9, 0], // the 0 ends the run and skips a byte
+            &number(2),
+            &[7, 0], // past the end of the source
+        ]
+        .concat();
+        let patch = with_footer(patch, &source, &target);
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_actions() {
+        let source = b"ABCDEFGH";
+        let target = b"ABxCDEFFFFGH";
+        let patch = [
+            b"BPS1".as_slice(),
+            &number(source.len()),
+            &number(target.len()),
+            &number(4),
+            b"meta",
+            &action(0, 2), // AB from the source
+            &action(1, 1), // x from the patch
+            b"x",
+            &action(2, 4), // CDEF from the source
+            &relative(2),
+            &action(3, 3), // FFF from the output, overlapping what is being written
+            &relative(6),
+            &action(2, 2), // GH from where the last source copy ended
+            &relative(0),
+        ]
+        .concat();
+        let patch = with_footer(patch, source, target);
+        assert_eq!(apply_patch(source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn checksum_mismatches() {
+        let source = [1, 2, 3];
+        let target = [1, 5, 3];
+        let body = [
+            b"UPS1".as_slice(),
+            &number(3),
+            &number(3),
+            &number(1),
+            &[2 ^ 5, 0],
+        ]
+        .concat();
+        let patch = with_footer(body.clone(), &source, &target);
+
+        let mut corrupted = patch.clone();
+        corrupted[4] ^= 1;
+        assert_eq!(
+            error(apply_patch(&source, &corrupted)),
+            "Patch is corrupted"
+        );
+        assert_eq!(
+            error(apply_patch(&[1, 2, 4], &patch)),
+            "Patch is for a different rom"
+        );
+        let wrong_target = with_footer(body, &source, &[0, 0, 0]);
+        assert_eq!(
+            error(apply_patch(&source, &wrong_target)),
+            "Patched rom doesn't match the checksum"
+        );
+    }
+
+    #[test]
+    fn number_overflows() {
+        let source = [1, 2, 3];
+        // a source size with more bits than fit in an address
+        let patch = [b"UPS1".as_slice(), &[0x7F; 10], &[0xFF]].concat();
+        let patch = with_footer(patch, &source, &source);
+        assert_eq!(error(apply_patch(&source, &patch)), "Patch is corrupted");
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert_eq!(error(apply_patch(&[0; 4], b"ZIP!")), "Unknown patch format");
+    }
+}
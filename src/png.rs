@@ -0,0 +1,114 @@
+use std::fs::{create_dir_all, write};
+use std::io::Result;
+use std::path::Path;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// Encodes 0x00RRGGBB pixels (the format of `GPU::video_buffer`) as an RGB PNG.
+/// The image data is stored uncompressed, which keeps the encoder tiny.
+pub fn encode_png(width: usize, height: usize, pixels: &[u32]) -> Vec<u8> {
+    assert!(pixels.len() == width * height);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &image_header(width, height));
+    write_chunk(&mut png, b"IDAT", &image_data(width, pixels));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+pub fn image_header(width: usize, height: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bit depth, truecolor, no interlace
+    header
+}
+
+/// Returns the zlib stream of an IDAT chunk holding the given rows of pixels.
+pub fn image_data(width: usize, pixels: &[u32]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(pixels.len() * 3 + pixels.len() / width.max(1));
+    for row in pixels.chunks(width) {
+        raw.push(0); // filter type: none
+        for pixel in row {
+            raw.push((pixel >> 16) as u8);
+            raw.push((pixel >> 8) as u8);
+            raw.push(*pixel as u8);
+        }
+    }
+    zlib_stored(&raw)
+}
+
+/// Encodes a frame as PNG, enlarging every pixel to `scale`x`scale` pixels.
+pub fn framebuffer_to_png(pixels: &[u32], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    let mut scaled = Vec::with_capacity(pixels.len() * scale * scale);
+    for row in pixels.chunks(width) {
+        let scaled_row: Vec<u32> = row
+            .iter()
+            .flat_map(|pixel| std::iter::repeat_n(*pixel, scale))
+            .collect();
+        for _ in 0..scale {
+            scaled.extend_from_slice(&scaled_row);
+        }
+    }
+    encode_png(width * scale, height * scale, &scaled)
+}
+
+pub fn write_png(path: &str, width: usize, height: usize, pixels: &[u32]) -> Result<()> {
+    let path = Path::new(path);
+    if let Some(folder) = path.parent() {
+        if !folder.exists() {
+            create_dir_all(folder)?;
+        }
+    }
+    write(path, encode_png(width, height, pixels))
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01]; // deflate, 32K window, no compression
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
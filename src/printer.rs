@@ -0,0 +1,383 @@
+use crate::png::write_png;
+use std::io::Result;
+use std::path::Path;
+
+// https://gbdev.io/pandocs/Gameboy_Printer.html
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_BREAK: u8 = 0x08;
+const COMMAND_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+const STATUS_PRINTING: u8 = 1 << 1;
+const STATUS_IMAGE_FULL: u8 = 1 << 2;
+const STATUS_UNPROCESSED: u8 = 1 << 3;
+
+const DEVICE_ID: u8 = 0x81;
+const PRINT_WIDTH: usize = 160;
+const BAND_SIZE: usize = 0x280; // 20x2 tiles
+const BAND_HEIGHT: usize = 16;
+const MAX_BANDS: usize = 9;
+const FEED_HEIGHT: usize = 16; // pixel rows per margin unit
+const BUSY_PACKETS: u8 = 4; // status polls answered with "printing" after a print
+const INK: [u32; 4] = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000];
+
+#[derive(Clone, Copy, PartialEq)]
+enum PacketState {
+    MagicHi,
+    MagicLo,
+    Command,
+    Compression,
+    LengthLo,
+    LengthHi,
+    Data,
+    ChecksumLo,
+    ChecksumHi,
+    DeviceId,
+    Status,
+}
+
+pub struct PrintedPage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+    pub path: Option<String>,
+}
+
+pub struct Printer {
+    output_dir: String,
+    pages: Vec<PrintedPage>,
+    page_open: bool,
+    // packet
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    packet_data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    // printer
+    image_data: Vec<u8>,
+    status: u8,
+    busy_packets: u8,
+}
+
+impl Printer {
+    pub fn new(output_dir: &str) -> Printer {
+        Printer {
+            output_dir: output_dir.to_string(),
+            pages: Vec::new(),
+            page_open: false,
+            state: PacketState::MagicHi,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet_data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            image_data: Vec::new(),
+            status: 0,
+            busy_packets: 0,
+        }
+    }
+
+    /// Pages printed so far. A page grows as long as prints end without a feed.
+    pub fn pages(&self) -> &[PrintedPage] {
+        &self.pages
+    }
+
+    /// Shifts one byte in from the Game Boy and returns the byte shifted out.
+    pub fn exchange(&mut self, data: u8) -> u8 {
+        let mut response = 0x00;
+
+        self.state = match self.state {
+            PacketState::MagicHi if data == 0x88 => PacketState::MagicLo,
+            PacketState::MagicHi => PacketState::MagicHi,
+            PacketState::MagicLo if data == 0x33 => PacketState::Command,
+            PacketState::MagicLo => PacketState::MagicHi,
+            PacketState::Command => {
+                self.command = data;
+                self.checksum = data as u16;
+                self.packet_data.clear();
+                PacketState::Compression
+            }
+            PacketState::Compression => {
+                self.compressed = data & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                PacketState::LengthLo
+            }
+            PacketState::LengthLo => {
+                self.length = data as u16;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                PacketState::LengthHi
+            }
+            PacketState::LengthHi => {
+                self.length |= (data as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                if self.length == 0 {
+                    PacketState::ChecksumLo
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::Data => {
+                self.packet_data.push(data);
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                if self.packet_data.len() == self.length as usize {
+                    PacketState::ChecksumLo
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::ChecksumLo => {
+                self.received_checksum = data as u16;
+                PacketState::ChecksumHi
+            }
+            PacketState::ChecksumHi => {
+                self.received_checksum |= (data as u16) << 8;
+                PacketState::DeviceId
+            }
+            PacketState::DeviceId => {
+                response = DEVICE_ID;
+                self.process_packet();
+                PacketState::Status
+            }
+            PacketState::Status => {
+                response = self.status;
+                if self.busy_packets > 0 {
+                    self.busy_packets -= 1;
+                    if self.busy_packets == 0 {
+                        self.status &= !STATUS_PRINTING;
+                    }
+                }
+                PacketState::MagicHi
+            }
+        };
+
+        response
+    }
+
+    fn process_packet(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.image_data.clear();
+                self.status = 0;
+                self.busy_packets = 0;
+            }
+            COMMAND_DATA => {
+                let data = if self.compressed {
+                    decompress(&self.packet_data)
+                } else {
+                    self.packet_data.clone()
+                };
+                self.image_data.extend(data);
+                self.image_data.truncate(BAND_SIZE * MAX_BANDS);
+                if !self.image_data.is_empty() {
+                    self.status |= STATUS_UNPROCESSED;
+                }
+                if self.image_data.len() == BAND_SIZE * MAX_BANDS {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            COMMAND_PRINT if self.packet_data.len() >= 4 => {
+                let margins = self.packet_data[1];
+                let palette = self.packet_data[2];
+                self.print(margins >> 4, margins & 0x0F, palette)
+                    .unwrap_or_else(|e| println!("Failed to write printout: {}", e));
+                self.image_data.clear();
+                self.status &= !(STATUS_UNPROCESSED | STATUS_IMAGE_FULL);
+                self.status |= STATUS_PRINTING;
+                self.busy_packets = BUSY_PACKETS;
+            }
+            COMMAND_BREAK => {
+                self.image_data.clear();
+                self.status &= !(STATUS_UNPROCESSED | STATUS_IMAGE_FULL);
+            }
+            COMMAND_STATUS => (),
+            _ => (),
+        }
+    }
+
+    fn print(&mut self, margin_before: u8, margin_after: u8, palette: u8) -> Result<()> {
+        // a palette of zero is treated like the default palette by the printer
+        let palette = if palette == 0 { 0xE4 } else { palette };
+
+        if margin_before > 0 || !self.page_open {
+            self.pages.push(PrintedPage {
+                width: PRINT_WIDTH,
+                height: 0,
+                pixels: Vec::new(),
+                path: None,
+            });
+            self.feed(margin_before as usize);
+        }
+
+        let bands = self.image_data.len() / BAND_SIZE;
+        let mut pixels = vec![INK[0]; PRINT_WIDTH * bands * BAND_HEIGHT];
+        let image_data = &self.image_data[..bands * BAND_SIZE];
+        for (tile, data) in image_data.chunks_exact(16).enumerate() {
+            let tile_x = (tile % 20) * 8;
+            let tile_y = (tile / 20) * 8;
+            for row in 0..8 {
+                let lo = data[row * 2];
+                let hi = data[row * 2 + 1];
+                for x in 0..8 {
+                    let color = ((hi >> (7 - x)) & 1) << 1 | ((lo >> (7 - x)) & 1);
+                    let shade = (palette >> (color * 2)) & 0b11;
+                    pixels[(tile_y + row) * PRINT_WIDTH + tile_x + x] = INK[shade as usize];
+                }
+            }
+        }
+
+        let page = self.pages.last_mut().unwrap();
+        page.pixels.extend(pixels);
+        page.height += bands * BAND_HEIGHT;
+        self.feed(margin_after as usize);
+        self.page_open = margin_after == 0;
+
+        self.save_page()
+    }
+
+    fn feed(&mut self, lines: usize) {
+        let page = self.pages.last_mut().unwrap();
+        page.pixels
+            .extend(vec![INK[0]; PRINT_WIDTH * FEED_HEIGHT * lines]);
+        page.height += FEED_HEIGHT * lines;
+    }
+
+    fn save_page(&mut self) -> Result<()> {
+        let output_dir = Path::new(&self.output_dir);
+        let page = self.pages.last_mut().unwrap();
+        if page.height == 0 {
+            return Ok(());
+        }
+        let path = match &page.path {
+            Some(path) => path.clone(),
+            None => {
+                let mut index = 1;
+                let mut path = output_dir.join(format!("print_{:04}.png", index));
+                while path.exists() {
+                    index += 1;
+                    path = output_dir.join(format!("print_{:04}.png", index));
+                }
+                let path = path.to_string_lossy().to_string();
+                page.path = Some(path.clone());
+                path
+            }
+        };
+        write_png(&path, page.width, page.height, &page.pixels)
+    }
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            let count = (control & 0x7F) as usize + 2;
+            if let Some(value) = data.get(i) {
+                result.extend(std::iter::repeat_n(*value, count));
+            }
+            i += 1;
+        } else {
+            let count = control as usize + 1;
+            let end = (i + count).min(data.len());
+            result.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sends a whole packet and returns the device id and status the printer answered with
+    fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![0x88, 0x33, command, compressed as u8];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        let checksum = packet[2..]
+            .iter()
+            .fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+        packet.extend_from_slice(&checksum.to_le_bytes());
+
+        for byte in packet {
+            assert_eq!(printer.exchange(byte), 0x00);
+        }
+        (printer.exchange(0x00), printer.exchange(0x00))
+    }
+
+    #[test]
+    fn prints_a_page() {
+        let folder = std::env::temp_dir().join("gb-emu-printer-test");
+        let _ = std::fs::remove_dir_all(&folder);
+        let mut printer = Printer::new(&folder.to_string_lossy());
+
+        assert_eq!(
+            send(&mut printer, COMMAND_INIT, false, &[]),
+            (DEVICE_ID, 0x00)
+        );
+
+        // a band of color 1, then a band of color 0 as five runs of 128 bytes
+        let band = [0xFF, 0x00].repeat(BAND_SIZE / 2);
+        let status = send(&mut printer, COMMAND_DATA, false, &band).1;
+        assert_eq!(status, STATUS_UNPROCESSED);
+        let runs = [0x80 | 126, 0x00].repeat(5);
+        assert_eq!(
+            send(&mut printer, COMMAND_DATA, true, &runs).1,
+            STATUS_UNPROCESSED
+        );
+        assert_eq!(
+            send(&mut printer, COMMAND_DATA, false, &[]).1,
+            STATUS_UNPROCESSED
+        );
+
+        // one sheet, no margin before and three after, default palette
+        let status = send(
+            &mut printer,
+            COMMAND_PRINT,
+            false,
+            &[0x01, 0x03, 0xE4, 0x40],
+        )
+        .1;
+        assert_eq!(status, STATUS_PRINTING);
+        for _ in 1..BUSY_PACKETS {
+            assert_eq!(
+                send(&mut printer, COMMAND_STATUS, false, &[]).1,
+                STATUS_PRINTING
+            );
+        }
+        assert_eq!(send(&mut printer, COMMAND_STATUS, false, &[]).1, 0x00);
+
+        let pages = printer.pages();
+        assert_eq!(pages.len(), 1);
+        let page = &pages[0];
+        assert_eq!(
+            (page.width, page.height),
+            (PRINT_WIDTH, (2 + 3) * BAND_HEIGHT)
+        );
+        assert_eq!(page.pixels[0], INK[1]);
+        assert_eq!(page.pixels[BAND_HEIGHT * PRINT_WIDTH], INK[0]);
+        assert!(Path::new(page.path.as_ref().unwrap()).exists());
+
+        // a corrupted packet is answered with a checksum error
+        for byte in [0x88, 0x33, COMMAND_STATUS, 0x00, 0x00, 0x00, 0xFF, 0x00] {
+            printer.exchange(byte);
+        }
+        assert_eq!(printer.exchange(0x00), DEVICE_ID);
+        assert_eq!(printer.exchange(0x00), STATUS_CHECKSUM_ERROR);
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+use crate::cpu::CPU;
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const REPORT_LINES: usize = 40;
+
+// rom bank and address
+type Location = (Option<usize>, u16);
+
+#[derive(Clone, Copy, Default)]
+pub struct FrameProfile {
+    pub cycles: u64,
+    pub busy: u64, // cycles the cpu was not halted
+}
+
+/// Counts the cycles spent per instruction, per function and per frame.
+/// Functions are the targets of calls, restarts and interrupts on the call stack.
+pub struct Profiler {
+    pub enabled: bool,
+    pub frames: Vec<FrameProfile>,
+    addresses: HashMap<Location, (u64, u64)>, // cycles and executions
+    stacks: HashMap<Vec<Location>, u64>,      // cycles per call stack, outermost call first
+    frame: FrameProfile,
+    frame_count: Option<u64>,
+    location: Location,
+    stack: Vec<Location>,
+    halted: bool,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            enabled: false,
+            frames: Vec::new(),
+            addresses: HashMap::new(),
+            stacks: HashMap::new(),
+            frame: FrameProfile::default(),
+            frame_count: None,
+            location: (None, 0),
+            stack: Vec::new(),
+            halted: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Profiler {
+            enabled: self.enabled,
+            ..Profiler::new()
+        };
+    }
+
+    /// Notes where the cpu is before an instruction.
+    pub fn sample(&mut self, cpu: &CPU) {
+        self.location = (cpu.mmu.rom_bank(cpu.pc), cpu.pc);
+        self.halted = cpu.halted;
+        self.stack.clear();
+        self.stack.extend(
+            cpu.call_stack
+                .frames
+                .iter()
+                .map(|frame| (frame.bank, frame.target)),
+        );
+
+        let frame_count = cpu.mmu.gpu.frame_count;
+        if self.frame_count.is_some_and(|count| count != frame_count) {
+            self.frames.push(std::mem::take(&mut self.frame));
+        }
+        self.frame_count = Some(frame_count);
+    }
+
+    /// Adds the cycles of the instruction at the last sample.
+    pub fn record(&mut self, cycles: u16) {
+        let cycles = cycles as u64;
+        let address = self.addresses.entry(self.location).or_default();
+        address.0 += cycles;
+        address.1 += 1;
+        match self.stacks.get_mut(self.stack.as_slice()) {
+            Some(total) => *total += cycles,
+            None => {
+                self.stacks.insert(self.stack.clone(), cycles);
+            }
+        }
+        self.frame.cycles += cycles;
+        if !self.halted {
+            self.frame.busy += cycles;
+        }
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.stacks.values().sum()
+    }
+
+    /// A summary of the frames, functions and addresses that took the most cycles.
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let total = self.total_cycles().max(1);
+        let percent = |cycles: u64| cycles as f64 * 100.0 / total as f64;
+        let mut output = String::new();
+        writeln!(output, "{} cycles in {} frames\n", total, self.frames.len()).unwrap();
+
+        let busy = |frame: &FrameProfile| frame.busy as f64 * 100.0 / frame.cycles.max(1) as f64;
+        if let Some((index, busiest)) = self
+            .frames
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, frame)| frame.busy)
+        {
+            let average = self.frames.iter().map(busy).sum::<f64>() / self.frames.len() as f64;
+            writeln!(
+                output,
+                "CPU busy {:.1}% per frame on average, {:.1}% at most in frame {}\n",
+                average,
+                busy(busiest),
+                index
+            )
+            .unwrap();
+        }
+
+        // self cycles of the innermost function, total cycles of every function on the stack
+        let mut functions: HashMap<Option<Location>, (u64, u64)> = HashMap::new();
+        for (stack, cycles) in &self.stacks {
+            functions.entry(stack.last().copied()).or_default().0 += cycles;
+            let mut seen = Vec::new();
+            for function in std::iter::once(None).chain(stack.iter().copied().map(Some)) {
+                if !seen.contains(&function) {
+                    seen.push(function);
+                    functions.entry(function).or_default().1 += cycles;
+                }
+            }
+        }
+        let mut functions = functions.into_iter().collect::<Vec<_>>();
+        functions.sort_by_key(|(_, (own, _))| std::cmp::Reverse(*own));
+        writeln!(
+            output,
+            "{:<32} {:>12} {:>7} {:>12} {:>7}",
+            "Function", "Self", "%", "Total", "%"
+        )
+        .unwrap();
+        for (function, (own, inclusive)) in functions.iter().take(REPORT_LINES) {
+            writeln!(
+                output,
+                "{:<32} {:>12} {:>6.2}% {:>12} {:>6.2}%",
+                function_name(*function, symbols),
+                own,
+                percent(*own),
+                inclusive,
+                percent(*inclusive)
+            )
+            .unwrap();
+        }
+
+        let mut addresses = self.addresses.iter().collect::<Vec<_>>();
+        addresses.sort_by_key(|(_, (cycles, _))| std::cmp::Reverse(*cycles));
+        writeln!(
+            output,
+            "\n{:<32} {:>12} {:>7} {:>12}",
+            "Address", "Cycles", "%", "Executed"
+        )
+        .unwrap();
+        for ((bank, address), (cycles, count)) in addresses.iter().take(REPORT_LINES) {
+            writeln!(
+                output,
+                "{:<32} {:>12} {:>6.2}% {:>12}",
+                symbols.format(*bank, *address),
+                cycles,
+                percent(*cycles),
+                count
+            )
+            .unwrap();
+        }
+        output
+    }
+
+    /// The cycles per call stack in the collapsed format of flamegraph.pl and inferno.
+    pub fn collapsed_stacks(&self, symbols: &Symbols) -> String {
+        let mut lines = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let names = std::iter::once(function_name(None, symbols))
+                    .chain(stack.iter().map(|f| function_name(Some(*f), symbols)))
+                    .collect::<Vec<String>>();
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect::<Vec<String>>();
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// code that wasn't called runs in the root
+fn function_name(function: Option<Location>, symbols: &Symbols) -> String {
+    match function {
+        Some((bank, address)) => symbols.format(bank, address),
+        None => "(root)".to_string(),
+    }
+}
@@ -0,0 +1,200 @@
+use crate::png::{image_data, image_header, write_chunk, SIGNATURE};
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Error, ErrorKind, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// 4194304 Hz / 70224 cycles per frame, about 59.7275 frames per second
+const CLOCK_SPEED: u64 = 4194304;
+const CYCLES_PER_FRAME: u64 = 70224;
+// frame delays are given in 1/10000 s and rounded so they add up to the exact frame rate
+const DELAY_DENOMINATOR: u16 = 10000;
+// the animation control chunk follows the signature and the 13 byte image header
+const ANIMATION_CONTROL_OFFSET: u64 = 8 + 12 + 13;
+
+/// Records every emulated frame to an uncompressed APNG, independent of the host frame rate.
+/// The pixels are stored exactly as the emulator drew them.
+/// Audio will be recorded alongside once the emulator has an APU.
+pub struct Recorder {
+    pub path: String,
+    pub frames: u64,
+    width: usize,
+    height: usize,
+    writer: BufWriter<File>,
+    last_frame: u64,
+    sequence: u32,
+}
+
+impl Recorder {
+    /// Starts a recording of the frames following the frame with the given number.
+    pub fn start(path: &str, width: usize, height: usize, frame_count: u64) -> Result<Recorder> {
+        if let Some(folder) = Path::new(path).parent() {
+            create_dir_all(folder)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut header = SIGNATURE.to_vec();
+        write_chunk(&mut header, b"IHDR", &image_header(width, height));
+        // the frame count is filled in when the recording is finished
+        write_chunk(&mut header, b"acTL", &animation_control(0));
+        writer.write_all(&header)?;
+
+        Ok(Recorder {
+            path: path.to_string(),
+            frames: 0,
+            width,
+            height,
+            writer,
+            last_frame: frame_count,
+            sequence: 0,
+        })
+    }
+
+    /// Adds the frame with the given number, unless it was already recorded.
+    pub fn capture(&mut self, frame_count: u64, pixels: &[u32]) -> Result<()> {
+        if self.last_frame == frame_count {
+            return Ok(());
+        }
+        self.last_frame = frame_count;
+        if pixels.len() != self.width * self.height {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Frame has {} pixels, the recording is {}x{}",
+                    pixels.len(),
+                    self.width,
+                    self.height
+                ),
+            ));
+        }
+
+        let mut control = Vec::with_capacity(26);
+        control.extend_from_slice(&self.next_sequence().to_be_bytes());
+        control.extend_from_slice(&image_header(self.width, self.height)[..8]);
+        control.extend_from_slice(&[0; 8]); // x and y offset
+        control.extend_from_slice(&frame_delay(self.frames).to_be_bytes());
+        control.extend_from_slice(&DELAY_DENOMINATOR.to_be_bytes());
+        control.extend_from_slice(&[0, 0]); // no disposal, replace the previous frame
+
+        let mut chunks = Vec::new();
+        write_chunk(&mut chunks, b"fcTL", &control);
+        let data = image_data(self.width, pixels);
+        if self.frames == 0 {
+            write_chunk(&mut chunks, b"IDAT", &data);
+        } else {
+            let mut frame_data = self.next_sequence().to_be_bytes().to_vec();
+            frame_data.extend_from_slice(&data);
+            write_chunk(&mut chunks, b"fdAT", &frame_data);
+        }
+        self.writer.write_all(&chunks)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        let mut end = Vec::new();
+        write_chunk(&mut end, b"IEND", &[]);
+        self.writer.write_all(&end)?;
+
+        let mut control = Vec::new();
+        write_chunk(
+            &mut control,
+            b"acTL",
+            &animation_control(self.frames as u32),
+        );
+        self.writer
+            .seek(SeekFrom::Start(ANIMATION_CONTROL_OFFSET))?;
+        self.writer.write_all(&control)?;
+        self.writer.flush()
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        self.sequence += 1;
+        self.sequence - 1
+    }
+}
+
+fn animation_control(frames: u32) -> Vec<u8> {
+    let mut control = frames.to_be_bytes().to_vec();
+    control.extend_from_slice(&0_u32.to_be_bytes()); // loop forever
+    control
+}
+
+// the delay of the given frame, so that every frame starts at the closest step to its real time
+fn frame_delay(frame: u64) -> u16 {
+    let start = |frame: u64| {
+        (frame * CYCLES_PER_FRAME * DELAY_DENOMINATOR as u64 + CLOCK_SPEED / 2) / CLOCK_SPEED
+    };
+    (start(frame + 1) - start(frame)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{read, remove_file};
+
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind = String::from_utf8(rest[4..8].to_vec()).unwrap();
+            chunks.push((kind, rest[8..8 + length].to_vec()));
+            rest = &rest[12 + length..];
+        }
+        chunks
+    }
+
+    // the pixels of a zlib stream with a single stored block
+    fn pixels(data: &[u8]) -> Vec<u32> {
+        let raw = &data[7..data.len() - 4];
+        raw.chunks(7)
+            .flat_map(|row| row[1..].chunks(3))
+            .map(|rgb| (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32)
+            .collect()
+    }
+
+    #[test]
+    fn records_exact_frames() {
+        let path =
+            std::env::temp_dir().join(format!("gb-emu-recording-{}.apng", std::process::id()));
+        let path = path.to_str().unwrap();
+        let first = [0x123456, 0xFFFFFF];
+        let second = [0x000000, 0x0A0B0C];
+
+        let mut recorder = Recorder::start(path, 2, 1, 0).unwrap();
+        recorder.capture(1, &first).unwrap();
+        recorder.capture(1, &second).unwrap();
+        assert!(recorder.capture(2, &[0; 3]).is_err());
+        recorder.capture(3, &second).unwrap();
+        recorder.finish().unwrap();
+        let png = read(path).unwrap();
+        remove_file(path).unwrap();
+
+        assert_eq!(&png[..8], &SIGNATURE);
+        let chunks = chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(
+            kinds,
+            ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]
+        );
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+        assert_eq!(chunks[1].1, [0, 0, 0, 2, 0, 0, 0, 0]);
+        assert_eq!(pixels(&chunks[3].1), first);
+        assert_eq!(&chunks[5].1[..4], &[0, 0, 0, 2]);
+        assert_eq!(pixels(&chunks[5].1[4..]), second);
+
+        // 167 and 168 ten thousandths of a second
+        assert_eq!(&chunks[2].1[..4], &[0, 0, 0, 0]);
+        assert_eq!(&chunks[4].1[..4], &[0, 0, 0, 1]);
+        assert_eq!(&chunks[2].1[20..24], &[0, 167, 0x27, 0x10]);
+        assert_eq!(&chunks[4].1[20..24], &[0, 168, 0x27, 0x10]);
+    }
+
+    #[test]
+    fn delays_add_up_to_the_frame_rate() {
+        // 4194304 frames of 70224 cycles take 70224 seconds
+        let total: u64 = (0..CLOCK_SPEED)
+            .map(|frame| frame_delay(frame) as u64)
+            .sum();
+        assert_eq!(total, CYCLES_PER_FRAME * DELAY_DENOMINATOR as u64);
+    }
+}
@@ -80,3 +80,9 @@ impl RTC {
         self.tac.test_bit(2)
     }
 }
+
+impl Default for RTC {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,174 @@
+use crate::mmu::MMU;
+use std::fmt;
+
+const RAM_BANK_SIZE: usize = 0x2000;
+
+/// How the bytes at a candidate are read, multi-byte values are little endian like on the cpu.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ValueKind {
+    Byte,
+    Word,
+    Bcd,     // two decimal digits in one byte
+    BcdWord, // four decimal digits in two bytes
+}
+
+impl ValueKind {
+    fn size(self) -> usize {
+        match self {
+            ValueKind::Byte | ValueKind::Bcd => 1,
+            ValueKind::Word | ValueKind::BcdWord => 2,
+        }
+    }
+
+    // bcd bytes with a digit above 9 can't hold the value
+    fn decode(self, bytes: &[u8]) -> Option<u32> {
+        let bcd = |byte: u8| {
+            (byte >> 4 < 10 && byte & 0x0F < 10).then(|| (byte >> 4) * 10 + (byte & 0x0F))
+        };
+        match self {
+            ValueKind::Byte => Some(bytes[0] as u32),
+            ValueKind::Word => Some(u16::from_le_bytes([bytes[0], bytes[1]]) as u32),
+            ValueKind::Bcd => bcd(bytes[0]).map(|value| value as u32),
+            ValueKind::BcdWord => Some(bcd(bytes[1])? as u32 * 100 + bcd(bytes[0])? as u32),
+        }
+    }
+}
+
+/// What a value has to do since the last search to stay a candidate.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comparison {
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    Equals(u32),
+}
+
+impl Comparison {
+    fn matches(self, previous: u32, value: u32) -> bool {
+        match self {
+            Comparison::Unchanged => value == previous,
+            Comparison::Changed => value != previous,
+            Comparison::Increased => value > previous,
+            Comparison::Decreased => value < previous,
+            Comparison::Equals(expected) => value == expected,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Area {
+    WorkRam,
+    HighRam,
+    CartridgeRam,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Location {
+    pub area: Area,
+    pub offset: usize, // into the area, across all banks of cartridge ram
+}
+
+impl Location {
+    pub fn address(&self) -> u16 {
+        match self.area {
+            Area::WorkRam => 0xC000 + self.offset as u16,
+            Area::HighRam => 0xFF80 + self.offset as u16,
+            Area::CartridgeRam => 0xA000 + (self.offset % RAM_BANK_SIZE) as u16,
+        }
+    }
+
+    /// The cartridge ram bank, `None` for work and high ram.
+    pub fn bank(&self) -> Option<usize> {
+        (self.area == Area::CartridgeRam).then_some(self.offset / RAM_BANK_SIZE)
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank() {
+            Some(bank) => write!(f, "{:02X}:{:04X}", bank, self.address()),
+            None => write!(f, "{:04X}", self.address()),
+        }
+    }
+}
+
+/// Narrows down where a game keeps a variable by comparing the ram between searches.
+pub struct RamSearch {
+    pub kind: ValueKind,
+    candidates: Vec<(Location, u32)>, // with the value at the last search
+    started: bool,
+}
+
+impl RamSearch {
+    pub fn new() -> RamSearch {
+        RamSearch {
+            kind: ValueKind::Byte,
+            candidates: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Takes every value in work, high and cartridge ram as a candidate.
+    pub fn start(&mut self, mmu: &MMU, kind: ValueKind) -> usize {
+        self.kind = kind;
+        self.started = true;
+        self.candidates.clear();
+        for area in [Area::WorkRam, Area::HighRam, Area::CartridgeRam] {
+            let length = area_bytes(mmu, area).len();
+            for offset in 0..(length + 1).saturating_sub(kind.size()) {
+                let location = Location { area, offset };
+                if let Some(value) = self.read(mmu, location) {
+                    self.candidates.push((location, value));
+                }
+            }
+        }
+        self.candidates.len()
+    }
+
+    /// Keeps the candidates whose value matches and returns how many are left.
+    pub fn filter(&mut self, mmu: &MMU, comparison: Comparison) -> usize {
+        let mut candidates = std::mem::take(&mut self.candidates);
+        candidates.retain_mut(|(location, previous)| match self.read(mmu, *location) {
+            Some(value) if comparison.matches(*previous, value) => {
+                *previous = value;
+                true
+            }
+            _ => false,
+        });
+        self.candidates = candidates;
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[(Location, u32)] {
+        &self.candidates
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// The current value at a location, `None` if the bytes aren't valid for the value kind.
+    pub fn read(&self, mmu: &MMU, location: Location) -> Option<u32> {
+        let bytes = area_bytes(mmu, location.area);
+        self.kind
+            .decode(bytes.get(location.offset..location.offset + self.kind.size())?)
+    }
+}
+
+impl Default for RamSearch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn area_bytes(mmu: &MMU, area: Area) -> &[u8] {
+    match area {
+        Area::WorkRam => &mmu.wram,
+        Area::HighRam => &mmu.hram,
+        Area::CartridgeRam => mmu
+            .cartrige
+            .as_ref()
+            .map_or(&[], |cartridge| cartridge.ram()),
+    }
+}
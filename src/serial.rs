@@ -0,0 +1,78 @@
+use crate::printer::Printer;
+use crate::traits::*;
+
+const CYCLES_PER_TRANSFER: u16 = 4096; // 8 bits at 8192 Hz
+
+pub struct Serial {
+    pub data: u8,    // SB
+    pub control: u8, // SC
+    pub transfer_counter: u16,
+    pub printer: Option<Printer>,
+}
+
+impl Memory for Serial {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0xFF01 => self.data,
+            0xFF02 => self.control | 0b0111_1110,
+            _ => panic!("Invalid Serial address"),
+        }
+    }
+
+    fn write(&mut self, address: usize, data: u8) {
+        match address {
+            0xFF01 => self.data = data,
+            0xFF02 => {
+                self.control = data;
+                if self.transfer_requested() {
+                    self.transfer_counter = 0;
+                    if self.printer.is_none() {
+                        print!("{}", self.data as char); // print serial output
+                    }
+                }
+            }
+            _ => panic!("Invalid Serial address"),
+        }
+    }
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0x00,
+            control: 0x7E,
+            transfer_counter: 0,
+            printer: None,
+        }
+    }
+
+    pub fn update(&mut self, cycles: u16) -> u8 {
+        let mut interrupt_flag = 0;
+
+        if self.transfer_requested() {
+            self.transfer_counter += cycles;
+            if self.transfer_counter >= CYCLES_PER_TRANSFER {
+                self.transfer_counter = 0;
+                self.data = match self.printer.as_mut() {
+                    Some(printer) => printer.exchange(self.data),
+                    None => 0xFF, // nothing connected
+                };
+                self.control.reset_bit(7);
+                interrupt_flag |= 1 << 3;
+            }
+        }
+
+        interrupt_flag
+    }
+
+    // only the internal clock is emulated, there is no link partner to drive an external one
+    fn transfer_requested(&self) -> bool {
+        self.control.test_bit(7) && self.control.test_bit(0)
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,463 @@
+use crate::gpu::{GPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+
+const SCREEN_X: usize = 48; // position of the game screen inside the border
+const SCREEN_Y: usize = 40;
+const TILES_X: usize = SCREEN_WIDTH / 8;
+const TILES_Y: usize = SCREEN_HEIGHT / 8;
+const BORDER_TILES_X: usize = SGB_WIDTH / 8;
+const BORDER_TILES_Y: usize = SGB_HEIGHT / 8;
+const BORDER_PALETTES: usize = 0x800; // offset of the border palettes in the PCT_TRN data
+
+const PACKET_SIZE: usize = 16;
+const MAX_PACKETS: usize = 7;
+const TRANSFER_SIZE: usize = 0x1000;
+const SYSTEM_PALETTES: usize = 512;
+const ATTRIBUTE_FILES: usize = 45;
+const ATTRIBUTE_FILE_SIZE: usize = TILES_X * TILES_Y / 4; // 2 bits per tile
+
+// https://gbdev.io/pandocs/SGB_Command_Summary.html
+const COMMAND_PAL01: u8 = 0x00;
+const COMMAND_PAL23: u8 = 0x01;
+const COMMAND_PAL03: u8 = 0x02;
+const COMMAND_PAL12: u8 = 0x03;
+const COMMAND_ATTR_BLK: u8 = 0x04;
+const COMMAND_ATTR_LIN: u8 = 0x05;
+const COMMAND_ATTR_DIV: u8 = 0x06;
+const COMMAND_ATTR_CHR: u8 = 0x07;
+const COMMAND_PAL_SET: u8 = 0x0A;
+const COMMAND_PAL_TRN: u8 = 0x0B;
+const COMMAND_MLT_REQ: u8 = 0x11;
+const COMMAND_CHR_TRN: u8 = 0x13;
+const COMMAND_PCT_TRN: u8 = 0x14;
+const COMMAND_ATTR_TRN: u8 = 0x15;
+const COMMAND_ATTR_SET: u8 = 0x16;
+const COMMAND_MASK_EN: u8 = 0x17;
+
+// palette 1-A, which the SGB uses until the game sets its own
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Transfer {
+    Palettes,
+    Tiles(usize), // first tile
+    Border,
+    Attributes,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mask {
+    None,
+    Freeze,
+    Black,
+    Color0,
+}
+
+pub struct SGB {
+    pub frame_buffer: Vec<u32>, // 256x224 output with the border
+    pub palettes: [[u16; 4]; 4],
+    pub attributes: [u8; TILES_X * TILES_Y], // palette of each tile on the screen
+    pub mask: Mask,
+    pub players: u8,
+    pub player: u8,
+    screen: Vec<u32>, // game screen, kept while frozen
+    system_palettes: Vec<[u16; 4]>,
+    attribute_files: Vec<u8>,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u8>,
+    // packet
+    packet: Vec<u8>,
+    bits: usize,
+    receiving: bool,
+    lines: u8, // last P14 and P15 written
+    // transfer waiting for the game to display its data
+    transfer: Option<Transfer>,
+    transfer_frame: u64,
+    frame_count: u64,
+}
+
+impl SGB {
+    pub fn new() -> SGB {
+        SGB {
+            frame_buffer: vec![0; SGB_WIDTH * SGB_HEIGHT],
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: [0; TILES_X * TILES_Y],
+            mask: Mask::None,
+            players: 1,
+            player: 0,
+            screen: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            system_palettes: vec![DEFAULT_PALETTE; SYSTEM_PALETTES],
+            attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE],
+            border_tiles: vec![0; 2 * TRANSFER_SIZE],
+            border_map: vec![0; TRANSFER_SIZE],
+            packet: Vec::with_capacity(PACKET_SIZE * MAX_PACKETS),
+            bits: 0,
+            receiving: false,
+            lines: 0x30,
+            transfer: None,
+            transfer_frame: 0,
+            frame_count: 0,
+        }
+    }
+
+    /// Receives command packets, which are sent bit by bit by pulsing P14 and P15.
+    pub fn write_joypad(&mut self, data: u8) {
+        let lines = data & 0x30;
+        match lines {
+            // both low resets the transfer and starts a packet
+            0x00 => {
+                if self.packet.len() >= PACKET_SIZE * MAX_PACKETS {
+                    self.packet.clear();
+                }
+                self.packet.extend_from_slice(&[0; PACKET_SIZE]);
+                self.bits = 0;
+                self.receiving = true;
+            }
+            // P15 low sends a one, P14 low a zero
+            0x10 | 0x20 if self.receiving && self.lines == 0x30 => self.receive_bit(lines == 0x10),
+            // the next controller is selected when P15 goes high
+            0x30 if !self.receiving && self.lines & 0x20 == 0 && self.players > 1 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => (),
+        }
+        self.lines = lines;
+    }
+
+    /// Low nibble of the joypad register while no keys are selected, if several controllers are enabled.
+    pub fn joypad_id(&self) -> Option<u8> {
+        if self.players > 1 {
+            Some(0x0F - self.player)
+        } else {
+            None
+        }
+    }
+
+    /// Renders the output and performs pending VRAM transfers whenever the GPU completes a frame.
+    pub fn update(&mut self, gpu: &GPU) {
+        if gpu.frame_count == self.frame_count {
+            return;
+        }
+        self.frame_count = gpu.frame_count;
+
+        // the data has to be on screen for a whole frame after the command
+        if let Some(transfer) = self.transfer {
+            if self.frame_count > self.transfer_frame + 1 {
+                self.transfer = None;
+                self.vram_transfer(transfer, &gpu.frame_shades);
+            }
+        }
+
+        self.render(&gpu.frame_shades);
+    }
+
+    fn receive_bit(&mut self, bit: bool) {
+        if self.bits == PACKET_SIZE * 8 {
+            // stop bit
+            self.receiving = false;
+            let packets = (self.packet[0] & 0x07).max(1) as usize;
+            if self.packet.len() >= packets * PACKET_SIZE {
+                self.run_command();
+                self.packet.clear();
+            }
+            return;
+        }
+
+        if bit {
+            let index = self.packet.len() - PACKET_SIZE + self.bits / 8;
+            self.packet[index] |= 1 << (self.bits % 8);
+        }
+        self.bits += 1;
+    }
+
+    fn run_command(&mut self) {
+        let data = std::mem::take(&mut self.packet);
+        match data[0] >> 3 {
+            COMMAND_PAL01 => self.set_palettes(&data, 0, 1),
+            COMMAND_PAL23 => self.set_palettes(&data, 2, 3),
+            COMMAND_PAL03 => self.set_palettes(&data, 0, 3),
+            COMMAND_PAL12 => self.set_palettes(&data, 1, 2),
+            COMMAND_ATTR_BLK => self.attribute_blocks(&data),
+            COMMAND_ATTR_LIN => self.attribute_lines(&data),
+            COMMAND_ATTR_DIV => self.attribute_divide(&data),
+            COMMAND_ATTR_CHR => self.attribute_tiles(&data),
+            COMMAND_PAL_SET => {
+                for i in 0..4 {
+                    let index = word(&data, 1 + i * 2) as usize % SYSTEM_PALETTES;
+                    self.palettes[i] = self.system_palettes[index];
+                }
+                self.share_color0();
+                if data[9] & 0x80 != 0 {
+                    self.apply_attribute_file(data[9] & 0x3F);
+                }
+                if data[9] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            COMMAND_MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            COMMAND_PAL_TRN => self.start_transfer(Transfer::Palettes),
+            COMMAND_CHR_TRN => self.start_transfer(Transfer::Tiles((data[1] as usize & 1) * 0x80)),
+            COMMAND_PCT_TRN => self.start_transfer(Transfer::Border),
+            COMMAND_ATTR_TRN => self.start_transfer(Transfer::Attributes),
+            COMMAND_ATTR_SET => {
+                self.apply_attribute_file(data[1] & 0x3F);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            COMMAND_MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::None,
+                }
+            }
+            _ => (), // sound, SNES code and the remaining commands are not emulated
+        }
+    }
+
+    fn set_palettes(&mut self, data: &[u8], first: usize, second: usize) {
+        self.palettes[0][0] = word(data, 1);
+        for i in 0..3 {
+            self.palettes[first][i + 1] = word(data, 3 + i * 2);
+            self.palettes[second][i + 1] = word(data, 9 + i * 2);
+        }
+        self.share_color0();
+    }
+
+    // color 0 of the first palette is used by all of them
+    fn share_color0(&mut self) {
+        for i in 1..4 {
+            self.palettes[i][0] = self.palettes[0][0];
+        }
+    }
+
+    fn attribute_blocks(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let control = set[0] & 0x07;
+            let inside = set[1] & 0x03;
+            let border = (set[1] >> 2) & 0x03;
+            let outside = (set[1] >> 4) & 0x03;
+            let (x1, y1) = (set[2] as usize & 0x1F, set[3] as usize & 0x1F);
+            let (x2, y2) = (set[4] as usize & 0x1F, set[5] as usize & 0x1F);
+
+            for y in 0..TILES_Y {
+                for x in 0..TILES_X {
+                    let in_block = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_border = in_block && (x == x1 || x == x2 || y == y1 || y == y2);
+                    // with only the inside or outside selected, the border takes that palette too
+                    let palette = if on_border {
+                        match control {
+                            _ if control & 0x02 != 0 => Some(border),
+                            0x01 => Some(inside),
+                            0x04 => Some(outside),
+                            _ => None,
+                        }
+                    } else if in_block {
+                        (control & 0x01 != 0).then_some(inside)
+                    } else {
+                        (control & 0x04 != 0).then_some(outside)
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * TILES_X + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attribute_lines(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for line in data[2..].iter().take(count) {
+            let index = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0x03;
+            if line & 0x80 != 0 {
+                if index < TILES_Y {
+                    self.attributes[index * TILES_X..(index + 1) * TILES_X].fill(palette);
+                }
+            } else if index < TILES_X {
+                for y in 0..TILES_Y {
+                    self.attributes[y * TILES_X + index] = palette;
+                }
+            }
+        }
+    }
+
+    fn attribute_divide(&mut self, data: &[u8]) {
+        let after = data[1] & 0x03;
+        let before = (data[1] >> 2) & 0x03;
+        let on_line = (data[1] >> 4) & 0x03;
+        let horizontal = data[1] & 0x40 != 0;
+        let line = (data[2] & 0x1F) as usize;
+
+        for y in 0..TILES_Y {
+            for x in 0..TILES_X {
+                let position = if horizontal { y } else { x };
+                self.attributes[y * TILES_X + x] = match position.cmp(&line) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+
+    fn attribute_tiles(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize & 0x1F, data[2] as usize & 0x1F);
+        let count = (word(data, 3) as usize).min(TILES_X * TILES_Y);
+        let vertical = data[5] & 0x01 != 0;
+
+        for i in 0..count {
+            let Some(byte) = data.get(6 + i / 4) else {
+                break;
+            };
+            if x < TILES_X && y < TILES_Y {
+                self.attributes[y * TILES_X + x] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+            }
+            if vertical {
+                y += 1;
+                if y == TILES_Y {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == TILES_X {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn apply_attribute_file(&mut self, file: u8) {
+        let file = file as usize;
+        if file >= ATTRIBUTE_FILES {
+            return;
+        }
+        let data = &self.attribute_files[file * ATTRIBUTE_FILE_SIZE..][..ATTRIBUTE_FILE_SIZE];
+        for (i, attribute) in self.attributes.iter_mut().enumerate() {
+            *attribute = (data[i / 4] >> (6 - (i % 4) * 2)) & 0x03;
+        }
+    }
+
+    fn start_transfer(&mut self, transfer: Transfer) {
+        self.transfer = Some(transfer);
+        self.transfer_frame = self.frame_count;
+    }
+
+    // the SGB reads the transferred data from the first 256 background tiles on the screen
+    fn vram_transfer(&mut self, transfer: Transfer, shades: &[u8]) {
+        let mut data = vec![0; TRANSFER_SIZE];
+        for (tile, tile_data) in data.chunks_exact_mut(16).enumerate() {
+            let tile_x = (tile % TILES_X) * 8;
+            let tile_y = (tile / TILES_X) * 8;
+            for row in 0..8 {
+                for x in 0..8 {
+                    let shade = shades[(tile_y + row) * SCREEN_WIDTH + tile_x + x];
+                    tile_data[row * 2] |= (shade & 1) << (7 - x);
+                    tile_data[row * 2 + 1] |= ((shade >> 1) & 1) << (7 - x);
+                }
+            }
+        }
+
+        match transfer {
+            Transfer::Palettes => {
+                for (i, palette) in self.system_palettes.iter_mut().enumerate() {
+                    for (c, color) in palette.iter_mut().enumerate() {
+                        *color = word(&data, i * 8 + c * 2);
+                    }
+                }
+            }
+            Transfer::Tiles(first) => {
+                self.border_tiles[first * 32..][..TRANSFER_SIZE].copy_from_slice(&data)
+            }
+            Transfer::Border => self.border_map.copy_from_slice(&data),
+            Transfer::Attributes => {
+                let length = self.attribute_files.len();
+                self.attribute_files.copy_from_slice(&data[..length]);
+            }
+        }
+    }
+
+    fn render(&mut self, shades: &[u8]) {
+        let backdrop = to_rgb(self.palettes[0][0]);
+
+        match self.mask {
+            Mask::None => {
+                for (i, pixel) in self.screen.iter_mut().enumerate() {
+                    let tile = (i / SCREEN_WIDTH / 8) * TILES_X + (i % SCREEN_WIDTH) / 8;
+                    let palette = self.attributes[tile] as usize;
+                    *pixel = to_rgb(self.palettes[palette][shades[i] as usize]);
+                }
+            }
+            Mask::Freeze => (),
+            Mask::Black => self.screen.fill(0x000000),
+            Mask::Color0 => self.screen.fill(backdrop),
+        }
+
+        self.frame_buffer.fill(backdrop);
+        for (y, row) in self.screen.chunks_exact(SCREEN_WIDTH).enumerate() {
+            let start = (SCREEN_Y + y) * SGB_WIDTH + SCREEN_X;
+            self.frame_buffer[start..start + SCREEN_WIDTH].copy_from_slice(row);
+        }
+
+        // the border is drawn above the screen, color 0 is transparent
+        for tile_y in 0..BORDER_TILES_Y {
+            for tile_x in 0..BORDER_TILES_X {
+                let entry = word(&self.border_map, (tile_y * BORDER_TILES_X + tile_x) * 2);
+                let tile = &self.border_tiles[(entry & 0xFF) as usize * 32..][..32];
+                let palette = BORDER_PALETTES + ((entry >> 10) & 0x03) as usize * 32;
+                let flip_x = entry & 0x4000 != 0;
+                let flip_y = entry & 0x8000 != 0;
+
+                for row in 0..8 {
+                    let tile_row = if flip_y { 7 - row } else { row };
+                    for x in 0..8 {
+                        let bit = if flip_x { x } else { 7 - x };
+                        // SNES 4bpp tiles store two bitplanes per half
+                        let color = (tile[tile_row * 2] >> bit) & 1
+                            | ((tile[tile_row * 2 + 1] >> bit) & 1) << 1
+                            | ((tile[16 + tile_row * 2] >> bit) & 1) << 2
+                            | ((tile[17 + tile_row * 2] >> bit) & 1) << 3;
+                        if color != 0 {
+                            let index = (tile_y * 8 + row) * SGB_WIDTH + tile_x * 8 + x;
+                            let color = word(&self.border_map, palette + color as usize * 2);
+                            self.frame_buffer[index] = to_rgb(color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for SGB {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn word(data: &[u8], index: usize) -> u16 {
+    u16::from_le_bytes([data[index], data[index + 1]])
+}
+
+// converts a 15 bit BGR color to 0x00RRGGBB
+fn to_rgb(color: u16) -> u32 {
+    let expand = |value: u16| ((value << 3) | (value >> 2)) as u32;
+    let r = expand(color & 0x1F);
+    let g = expand((color >> 5) & 0x1F);
+    let b = expand((color >> 10) & 0x1F);
+    (r << 16) | (g << 8) | b
+}
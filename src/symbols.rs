@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::io::Result;
+
+/// Labels from a symbol file, as written by RGBDS (`rgblink -n`), no$gmb and wla-dx.
+/// Every line holds `BANK:ADDRESS NAME`, other lines and `;` comments are skipped.
+#[derive(Clone)]
+pub struct Symbols {
+    labels: BTreeMap<(usize, u16), String>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols {
+            labels: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Symbols> {
+        Ok(Symbols::parse(&read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> Symbols {
+        let mut symbols = Symbols::new();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let (Some(location), Some(name)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let Some((bank, address)) = location.split_once(':') else {
+                continue;
+            };
+            if let (Ok(bank), Ok(address)) = (
+                usize::from_str_radix(bank, 16),
+                u16::from_str_radix(address, 16),
+            ) {
+                symbols.insert(bank, address, name);
+            }
+        }
+        symbols
+    }
+
+    pub fn insert(&mut self, bank: usize, address: u16, name: &str) {
+        self.labels
+            .insert((bank_key(Some(bank), address), address), name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// The label at exactly this address.
+    pub fn label(&self, bank: Option<usize>, address: u16) -> Option<&str> {
+        self.labels
+            .get(&(bank_key(bank, address), address))
+            .map(|name| name.as_str())
+    }
+
+    /// The closest label at or before an address as `label+offset`, within the same memory area.
+    pub fn lookup(&self, bank: Option<usize>, address: u16) -> Option<String> {
+        let key = bank_key(bank, address);
+        let ((_, start), name) = self.labels.range((key, 0)..=(key, address)).next_back()?;
+        if area(*start) != area(address) {
+            return None;
+        }
+        match address - start {
+            0 => Some(name.clone()),
+            offset => Some(format!("{}+{:X}", name, offset)),
+        }
+    }
+
+    /// An address as `bank:label+offset`, or `bank:address` without a label.
+    pub fn format(&self, bank: Option<usize>, address: u16) -> String {
+        let bank_text = bank.map_or("--".to_string(), |bank| format!("{:02X}", bank));
+        match self.lookup(bank, address) {
+            Some(label) => format!("{}:{}", bank_text, label),
+            None => format!("{}:{:04X}", bank_text, address),
+        }
+    }
+
+    /// Finds the bank and address of `label` or `label+offset`.
+    pub fn resolve(&self, text: &str) -> Option<(usize, u16)> {
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (name, u16::from_str_radix(offset, 16).ok()?),
+            None => (text, 0),
+        };
+        self.labels
+            .iter()
+            .find(|(_, label)| label.as_str() == name)
+            .map(|((bank, address), _)| (*bank, address.wrapping_add(offset)))
+    }
+}
+
+impl Default for Symbols {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// only the switchable rom bank tells labels apart by bank, ram labels match in any bank
+fn bank_key(bank: Option<usize>, address: u16) -> usize {
+    match address {
+        0x4000..=0x7FFF => bank.unwrap_or(0),
+        _ => 0,
+    }
+}
+
+// labels don't reach past the end of a memory area
+fn area(address: u16) -> u8 {
+    match address {
+        0x0000..=0x3FFF => 0,
+        0x4000..=0x7FFF => 1,
+        0x8000..=0x9FFF => 2,
+        0xA000..=0xBFFF => 3,
+        0xC000..=0xDFFF => 4,
+        0xE000..=0xFDFF => 5,
+        0xFE00..=0xFEFF => 6,
+        0xFF00..=0xFF7F => 7,
+        _ => 8,
+    }
+}
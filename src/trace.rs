@@ -0,0 +1,160 @@
+use crate::cpu::CPU;
+use crate::disasm::decode;
+use crate::symbols::Symbols;
+use std::collections::VecDeque;
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    // https://github.com/robert/gameboy-doctor
+    Doctor,
+    Full,
+}
+
+impl TraceFormat {
+    pub fn parse(name: &str) -> Result<TraceFormat> {
+        match name {
+            "doctor" => Ok(TraceFormat::Doctor),
+            "full" => Ok(TraceFormat::Full),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown trace format: {}", name),
+            )),
+        }
+    }
+}
+
+enum TraceOutput {
+    File(BufWriter<File>),
+    Ring(VecDeque<String>, usize),
+}
+
+/// Logs the CPU state before every instruction.
+pub struct Tracer {
+    pub format: TraceFormat,
+    pub pc_range: Option<RangeInclusive<u16>>,
+    pub bank: Option<usize>, // only instructions in this rom bank
+    pub symbols: Symbols,    // shown instead of addresses in the full format
+    output: TraceOutput,
+}
+
+impl Tracer {
+    pub fn to_file(path: &str, format: TraceFormat) -> Result<Tracer> {
+        if let Some(folder) = Path::new(path).parent() {
+            create_dir_all(folder)?;
+        }
+        let file = File::create(path)?;
+        Ok(Tracer::new(format, TraceOutput::File(BufWriter::new(file))))
+    }
+
+    /// Keeps only the last `capacity` lines in memory.
+    pub fn ring_buffer(capacity: usize, format: TraceFormat) -> Tracer {
+        let capacity = capacity.max(1);
+        let lines = VecDeque::with_capacity(capacity);
+        Tracer::new(format, TraceOutput::Ring(lines, capacity))
+    }
+
+    fn new(format: TraceFormat, output: TraceOutput) -> Tracer {
+        Tracer {
+            format,
+            pc_range: None,
+            bank: None,
+            symbols: Symbols::new(),
+            output,
+        }
+    }
+
+    /// Lines kept by a ring buffer, oldest first.
+    pub fn lines(&self) -> impl Iterator<Item = &String> {
+        let lines = match &self.output {
+            TraceOutput::Ring(lines, _) => Some(lines.iter()),
+            TraceOutput::File(_) => None,
+        };
+        lines.into_iter().flatten()
+    }
+
+    pub fn log(&mut self, cpu: &CPU) -> Result<()> {
+        let bank = cpu.mmu.rom_bank(cpu.pc);
+        if self
+            .pc_range
+            .as_ref()
+            .is_some_and(|range| !range.contains(&cpu.pc))
+            || self.bank.is_some_and(|filter| bank != Some(filter))
+        {
+            return Ok(());
+        }
+
+        let line = match self.format {
+            TraceFormat::Doctor => format!(
+                "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                cpu.a,
+                cpu.f,
+                cpu.b,
+                cpu.c,
+                cpu.d,
+                cpu.e,
+                cpu.h,
+                cpu.l,
+                cpu.sp,
+                cpu.pc,
+                cpu.mmu.peek(cpu.pc),
+                cpu.mmu.peek(cpu.pc.wrapping_add(1)),
+                cpu.mmu.peek(cpu.pc.wrapping_add(2)),
+                cpu.mmu.peek(cpu.pc.wrapping_add(3)),
+            ),
+            TraceFormat::Full => {
+                let memory = [0, 1, 2].map(|i| cpu.mmu.peek(cpu.pc.wrapping_add(i)));
+                let instruction = decode(&memory, cpu.pc);
+                let bytes = memory[..instruction.length as usize]
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                let flags = [(7, 'Z'), (6, 'N'), (5, 'H'), (4, 'C')]
+                    .iter()
+                    .map(|(bit, name)| if cpu.f & (1 << bit) != 0 { *name } else { '-' })
+                    .collect::<String>();
+                // labels are wider than addresses
+                let width = if self.symbols.is_empty() { 7 } else { 24 };
+                format!(
+                    "{:<width$}  {:<8}  {:<20}  A:{:02X} F:{} BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X} IME:{} CY:{}",
+                    self.symbols.format(bank, cpu.pc),
+                    bytes,
+                    instruction.mnemonic,
+                    cpu.a,
+                    flags,
+                    cpu.b,
+                    cpu.c,
+                    cpu.d,
+                    cpu.e,
+                    cpu.h,
+                    cpu.l,
+                    cpu.sp,
+                    cpu.interrupt_master_enable as u8,
+                    cpu.cycles,
+                )
+            }
+        };
+
+        match &mut self.output {
+            TraceOutput::File(writer) => writeln!(writer, "{}", line),
+            TraceOutput::Ring(lines, capacity) => {
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        match &mut self.output {
+            TraceOutput::File(writer) => writer.flush(),
+            TraceOutput::Ring(_, _) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::gpu::{Tile, GPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::traits::*;
+use std::fmt::Write;
+
+const GAP_COLOR: u32 = 0x202020;
+const VIEWPORT_COLOR: u32 = 0xFF0000;
+const WINDOW_COLOR: u32 = 0x0080FF;
+
+const TILE_COLUMNS: usize = 16;
+const TILE_ROWS: usize = 24;
+const MAP_SIZE: usize = 256;
+const MAP_GAP: usize = 8;
+const SPRITE_COLUMNS: usize = 8;
+const SPRITE_ROWS: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum View {
+    Tiles,
+    Maps,
+    Sprites,
+}
+
+pub const VIEWS: [View; 3] = [View::Tiles, View::Maps, View::Sprites];
+
+/// How the tile viewer colors the four color indices of a tile.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TilePalette {
+    Raw,
+    Background, // BGP
+    Object0,    // OBP0
+    Object1,    // OBP1
+}
+
+impl TilePalette {
+    pub fn next(self) -> TilePalette {
+        match self {
+            TilePalette::Raw => TilePalette::Background,
+            TilePalette::Background => TilePalette::Object0,
+            TilePalette::Object0 => TilePalette::Object1,
+            TilePalette::Object1 => TilePalette::Raw,
+        }
+    }
+
+    fn colors(self, gpu: &GPU) -> [u32; 4] {
+        let (registers, colors) = match self {
+            TilePalette::Raw => return gpu.colors.bg,
+            TilePalette::Background => (gpu.bg_palette, gpu.colors.bg),
+            TilePalette::Object0 => (gpu.obj_palette_0, gpu.colors.obj0),
+            TilePalette::Object1 => (gpu.obj_palette_1, gpu.colors.obj1),
+        };
+        [0, 1, 2, 3].map(|color| colors[GPU::get_shade(registers, color) as usize])
+    }
+}
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![GAP_COLOR; width * height],
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: u32) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    // color 0 is left out for sprites
+    fn draw_tile(&mut self, tile: &Tile, x: usize, y: usize, colors: [u32; 4], transparent: bool) {
+        for (row, line) in tile.iter().enumerate() {
+            for (column, color) in line.iter().enumerate() {
+                if !(transparent && *color == 0) {
+                    self.set(x + column, y + row, colors[*color as usize]);
+                }
+            }
+        }
+    }
+}
+
+/// All 384 tiles of VRAM, 16 per row with a line in between.
+pub fn render_tiles(gpu: &GPU, palette: TilePalette) -> Image {
+    let mut image = Image::new(TILE_COLUMNS * 9 + 1, TILE_ROWS * 9 + 1);
+    let colors = palette.colors(gpu);
+    for (index, tile) in gpu.tiles.iter().enumerate() {
+        let x = index % TILE_COLUMNS * 9 + 1;
+        let y = index / TILE_COLUMNS * 9 + 1;
+        image.draw_tile(tile, x, y, colors, false);
+    }
+    image
+}
+
+/// Both 32x32 tile maps side by side, with the visible background and window outlined.
+pub fn render_maps(gpu: &GPU) -> Image {
+    let mut image = Image::new(MAP_SIZE * 2 + MAP_GAP, MAP_SIZE);
+    let colors = TilePalette::Background.colors(gpu);
+    let unsigned_tiles = gpu.lcd_control.test_bit(4);
+
+    for map in 0..2 {
+        let left = map * (MAP_SIZE + MAP_GAP);
+        for index in 0..32 * 32 {
+            let tile_index = gpu.vram[0x1800 + map * 0x400 + index];
+            // tiles at 0x9000 are numbered from -128 to 127 without LCDC bit 4
+            let tile = if unsigned_tiles {
+                tile_index as usize
+            } else {
+                (256 + tile_index as i8 as isize) as usize
+            };
+            let x = left + index % 32 * 8;
+            let y = index / 32 * 8;
+            image.draw_tile(&gpu.tiles[tile], x, y, colors, false);
+        }
+    }
+
+    // the viewport wraps around the map
+    let background_left = gpu.lcd_control.test_bit(3) as usize * (MAP_SIZE + MAP_GAP);
+    let (scroll_x, scroll_y) = (gpu.scroll_x as usize, gpu.scroll_y as usize);
+    for x in 0..SCREEN_WIDTH {
+        for y in [0, SCREEN_HEIGHT - 1] {
+            let map_x = background_left + (scroll_x + x) % MAP_SIZE;
+            image.set(map_x, (scroll_y + y) % MAP_SIZE, VIEWPORT_COLOR);
+        }
+    }
+    for y in 0..SCREEN_HEIGHT {
+        for x in [0, SCREEN_WIDTH - 1] {
+            let map_x = background_left + (scroll_x + x) % MAP_SIZE;
+            image.set(map_x, (scroll_y + y) % MAP_SIZE, VIEWPORT_COLOR);
+        }
+    }
+
+    // the window shows its map from the top left corner
+    let window_x = gpu.window_x as usize;
+    let window_y = gpu.window_y as usize;
+    if gpu.lcd_control.test_bit(5) && window_x <= 166 && window_y < SCREEN_HEIGHT {
+        let window_left = gpu.lcd_control.test_bit(6) as usize * (MAP_SIZE + MAP_GAP);
+        let width = SCREEN_WIDTH + 7 - window_x.max(7);
+        let height = SCREEN_HEIGHT - window_y;
+        for x in 0..width {
+            image.set(window_left + x, 0, WINDOW_COLOR);
+            image.set(window_left + x, height - 1, WINDOW_COLOR);
+        }
+        for y in 0..height {
+            image.set(window_left, y, WINDOW_COLOR);
+            image.set(window_left + width - 1, y, WINDOW_COLOR);
+        }
+    }
+    image
+}
+
+/// The 40 sprites of OAM in 5 rows, drawn with their palette and flips.
+pub fn render_sprites(gpu: &GPU) -> Image {
+    let mut image = Image::new(SPRITE_COLUMNS * 10 + 2, SPRITE_ROWS * 18 + 2);
+    let tall = gpu.lcd_control.test_bit(2);
+    for (index, sprite) in gpu.sprites.iter().enumerate() {
+        let colors = if sprite.palette {
+            TilePalette::Object1.colors(gpu)
+        } else {
+            TilePalette::Object0.colors(gpu)
+        };
+        // 8x16 sprites ignore bit 0 of the tile index
+        let tiles = if tall {
+            let first = (sprite.tile_index & 0xFE) as usize;
+            if sprite.y_flip {
+                vec![first + 1, first]
+            } else {
+                vec![first, first + 1]
+            }
+        } else {
+            vec![sprite.tile_index as usize]
+        };
+
+        let x = index % SPRITE_COLUMNS * 10 + 2;
+        let y = index / SPRITE_COLUMNS * 18 + 2;
+        for (part, tile) in tiles.iter().enumerate() {
+            let mut tile = gpu.tiles[*tile];
+            if sprite.x_flip {
+                tile.iter_mut().for_each(|row| row.reverse());
+            }
+            if sprite.y_flip {
+                tile.reverse();
+            }
+            image.draw_tile(&tile, x, y + part * 8, colors, true);
+        }
+    }
+    image
+}
+
+/// Position, tile and flags of every sprite.
+pub fn sprite_table(gpu: &GPU) -> String {
+    let mut table = String::from(" #    X    Y  Tile  Flags\n");
+    for (index, sprite) in gpu.sprites.iter().enumerate() {
+        let flags = [
+            (sprite.palette, "OBP1"),
+            (sprite.x_flip, "XFLIP"),
+            (sprite.y_flip, "YFLIP"),
+            (sprite.bg_priority, "BEHIND"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect::<Vec<&str>>();
+        // positions on screen
+        writeln!(
+            table,
+            "{:2} {:4} {:4}  ${:02X}   {}",
+            index,
+            sprite.x as i16 - 8,
+            sprite.y as i16 - 16,
+            sprite.tile_index,
+            if flags.is_empty() {
+                "-".to_string()
+            } else {
+                flags.join(" ")
+            }
+        )
+        .unwrap();
+    }
+    table
+}
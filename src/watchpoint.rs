@@ -0,0 +1,174 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub pause: bool, // break into the debugger, otherwise only the callbacks see it
+}
+
+impl Watchpoint {
+    /// Watches the given kinds of access, any combination of `r`, `w` and `x`.
+    pub fn new(range: RangeInclusive<u16>, access: &str) -> Watchpoint {
+        Watchpoint {
+            range,
+            read: access.contains('r'),
+            write: access.contains('w'),
+            execute: access.contains('x'),
+            pause: true,
+        }
+    }
+
+    fn watches(&self, access: Access, address: u16) -> bool {
+        let kind = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        };
+        kind && self.range.contains(&address)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = [(self.read, 'r'), (self.write, 'w'), (self.execute, 'x')]
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, name)| *name)
+            .collect::<String>();
+        write!(
+            f,
+            "{:04X}-{:04X} {}",
+            self.range.start(),
+            self.range.end(),
+            access
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WatchHit {
+    pub access: Access,
+    pub pc: u16, // instruction that made the access
+    pub address: u16,
+    pub old: u8,
+    pub new: u8, // same as old for reads and execution
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Write => write!(
+                f,
+                "Write to {:04X} at PC {:04X}: {:02X} -> {:02X}",
+                self.address, self.pc, self.old, self.new
+            ),
+            _ => write!(
+                f,
+                "{:?} of {:04X} at PC {:04X}: {:02X}",
+                self.access, self.address, self.pc, self.old
+            ),
+        }
+    }
+}
+
+pub type WatchCallback = Box<dyn FnMut(&WatchHit)>;
+
+/// Watchpoints on memory accesses of the cpu. Accesses are collected while an instruction runs
+/// and reported to the callbacks afterwards.
+pub struct Watchpoints {
+    pub active: bool, // checked before anything else, only set while there are watchpoints
+    pub pc: u16,
+    pub triggered: Option<WatchHit>, // the last hit that pauses
+    list: Vec<Watchpoint>,
+    callbacks: Vec<WatchCallback>,
+    hits: RefCell<Vec<WatchHit>>, // reads only borrow the mmu
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints {
+            list: Vec::new(),
+            active: false,
+            pc: 0,
+            triggered: None,
+            callbacks: Vec::new(),
+            hits: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.list
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) -> usize {
+        self.list.push(watchpoint);
+        self.update_active();
+        self.list.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watchpoint> {
+        let watchpoint = (index < self.list.len()).then(|| self.list.remove(index));
+        self.update_active();
+        watchpoint
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.update_active();
+    }
+
+    /// Registers a function that is called with every hit.
+    pub fn on_hit(&mut self, callback: impl FnMut(&WatchHit) + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    fn update_active(&mut self) {
+        self.active = !self.list.is_empty();
+    }
+
+    pub fn check(&self, access: Access, address: u16, old: u8, new: u8) {
+        if self.list.iter().any(|w| w.watches(access, address)) {
+            self.hits.borrow_mut().push(WatchHit {
+                access,
+                pc: self.pc,
+                address,
+                old,
+                new,
+            });
+        }
+    }
+
+    /// Hands the hits of the last instruction to the callbacks.
+    pub fn dispatch(&mut self) {
+        let hits = std::mem::take(self.hits.get_mut());
+        for hit in hits {
+            for callback in self.callbacks.iter_mut() {
+                callback(&hit);
+            }
+            let pause = self
+                .list
+                .iter()
+                .any(|w| w.pause && w.watches(hit.access, hit.address));
+            if pause {
+                self.triggered = Some(hit);
+            }
+        }
+    }
+}
+
+impl Default for Watchpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}